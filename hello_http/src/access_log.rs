@@ -0,0 +1,228 @@
+use crate::{
+    date::{format_log_date, format_rfc3339},
+    Context,
+    Method,
+    Middleware,
+    Request,
+    Response
+};
+use std::{
+    fmt::Write as _,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+    sync::{Mutex, PoisonError},
+    time::SystemTime
+};
+
+/// How an access log writes its entries. Every entry is one line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// `127.0.0.1 - - [06/Nov/1994:08:49:37 +0000] "GET / HTTP/1.1" 200 1234 512`
+    ///
+    /// The Common Log Format, followed by the duration in microseconds, like Apache's `%D`.
+    #[default]
+    Common,
+    /// The Common Log Format with the `Referer` and `User-Agent` headers, followed by the duration in microseconds.
+    Combined,
+    /// One JSON object per line, e.g. for log collectors that don't want to parse the other formats.
+    Json
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    /// Reads the name of a format as it's written in a config file, e.g. `combined`.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "common" => Ok(LogFormat::Common),
+            "combined" => Ok(LogFormat::Combined),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format `{name}`, expected `common`, `combined` or `json`"))
+        }
+    }
+}
+
+/// A middleware that writes one line per request to a file or to stderr.
+///
+/// Entries are written after the handler returned, so a streamed body that hasn't been sent yet is logged with an unknown size.
+pub struct AccessLog {
+    format: LogFormat,
+    // The lock makes sure the lines of concurrent requests don't end up mixed with each other.
+    out: Mutex<Box<dyn Write + Send>>
+}
+
+impl AccessLog {
+    /// Writes the entries to any writer, e.g. a buffer in a test.
+    pub fn new<W>(format: LogFormat, out: W) -> Self
+        where W: Write + Send + 'static {
+        AccessLog {
+            format,
+            out: Mutex::new(Box::new(out))
+        }
+    }
+
+    pub fn stderr(format: LogFormat) -> Self {
+        Self::new(format, io::stderr())
+    }
+
+    /// Appends the entries to the file at `path`, which is created if it doesn't exist yet.
+    pub fn file(format: LogFormat, path: impl AsRef<Path>) -> io::Result<Self> {
+        let file: File = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self::new(format, file))
+    }
+
+    fn entry(&self, request: &Request, response: &Response, context: &Context) -> String {
+        let now = SystemTime::now();
+        let host = request
+            .peer_addr()
+            .map_or_else(|| String::from("-"), |address| address.ip().to_string());
+        let size = body_size(request, response);
+        let micros = context.elapsed().as_micros();
+        match self.format {
+            LogFormat::Common | LogFormat::Combined => {
+                let mut line = format!(
+                    "{host} - {} [{}] \"{} {} {}\" {} {}",
+                    context.user().map_or_else(|| String::from("-"), quote_free),
+                    format_log_date(now),
+                    request.method(),
+                    quote_free(request.target()),
+                    request.version(),
+                    response.status().code(),
+                    size.map_or_else(|| String::from("-"), |size| size.to_string())
+                );
+                if self.format == LogFormat::Combined {
+                    let header = |name| request.header(name).map_or_else(|| String::from("-"), quote_free);
+                    let _ = write!(line, " \"{}\" \"{}\"", header("Referer"), header("User-Agent"));
+                }
+                let _ = write!(line, " {micros}");
+                line
+            }
+            LogFormat::Json => {
+                let mut line = String::from("{");
+                let _ = write!(line, "\"time\":\"{}\"", format_rfc3339(now));
+                let _ = write!(line, ",\"remote_addr\":{}", json_string_or_null(request.peer_addr().map(|address| address.ip().to_string()).as_deref()));
+                let _ = write!(line, ",\"user\":{}", json_string_or_null(context.user()));
+                let _ = write!(line, ",\"method\":\"{}\"", request.method());
+                let _ = write!(line, ",\"path\":{}", json_string(request.path()));
+                let _ = write!(line, ",\"version\":\"{}\"", request.version());
+                let _ = write!(line, ",\"status\":{}", response.status().code());
+                let _ = write!(line, ",\"size\":{}", size.map_or_else(|| String::from("null"), |size| size.to_string()));
+                let _ = write!(line, ",\"duration_us\":{micros}");
+                let _ = write!(line, ",\"referer\":{}", json_string_or_null(request.header("Referer")));
+                let _ = write!(line, ",\"user_agent\":{}", json_string_or_null(request.header("User-Agent")));
+                line.push('}');
+                line
+            }
+        }
+    }
+}
+
+impl Middleware for AccessLog {
+    fn after(&self, request: &Request, response: Response, context: &mut Context) -> Response {
+        let mut line = self.entry(request, &response, context);
+        line.push('\n');
+        let mut out = self.out.lock().unwrap_or_else(PoisonError::into_inner);
+        // A log that can't be written shouldn't cost the client its response.
+        let _ = out
+            .write_all(line.as_bytes())
+            .and_then(|()| out.flush());
+        response
+    }
+}
+
+// `HEAD` responses announce a body, but never send it.
+fn body_size(request: &Request, response: &Response) -> Option<u64> {
+    if request.method() == Method::Head {
+        return Some(0);
+    }
+    response.body().len()
+}
+
+// Clients choose the target and the headers, so quotes and control characters are escaped to keep them from faking a second entry.
+fn quote_free(value: &str) -> String {
+    value.escape_default().to_string()
+}
+
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c)
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn json_string_or_null(value: Option<&str>) -> String {
+    value.map_or_else(|| String::from("null"), json_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Chain, Handler, Params, StatusCode};
+    use std::sync::Arc;
+
+    // A writer that keeps what was written, so the test can look at it afterwards.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn log_one(format: LogFormat, raw: &str) -> String {
+        let buffer = Buffer::default();
+        let chain = Chain::new(|_: &Request, _: &Params| Response::text(StatusCode::Ok, "hello"))
+            .with_middleware(AccessLog::new(format, buffer.clone()));
+        let mut request = Request::parse(&mut raw.as_bytes()).unwrap();
+        request.set_peer_addr(Some("192.0.2.7:51000".parse().unwrap()));
+        chain.handle(&request, &Params::default());
+        let written = buffer.0.lock().unwrap().clone();
+        String::from_utf8(written).unwrap()
+    }
+
+    #[test]
+    fn writes_common_and_combined_lines() {
+        let raw = "GET /posts?page=2 HTTP/1.1\r\nUser-Agent: curl/8.0 \"quoted\"\r\n\r\n";
+
+        let common = log_one(LogFormat::Common, raw);
+        assert!(common.starts_with("192.0.2.7 - - ["));
+        assert!(common.contains("] \"GET /posts?page=2 HTTP/1.1\" 200 5 "));
+        assert!(common.ends_with('\n'));
+        assert_eq!(1, common.lines().count());
+
+        let combined = log_one(LogFormat::Combined, raw);
+        assert!(combined.contains(" 200 5 \"-\" \"curl/8.0 \\\"quoted\\\"\" "));
+    }
+
+    #[test]
+    fn writes_json_lines() {
+        let line = log_one(LogFormat::Json, "HEAD /a\"b HTTP/1.0\r\n\r\n");
+
+        assert!(line.starts_with("{\"time\":\""));
+        assert!(line.contains(",\"remote_addr\":\"192.0.2.7\",\"user\":null,\"method\":\"HEAD\",\"path\":\"/a\\\"b\",\"version\":\"HTTP/1.0\",\"status\":200,\"size\":0,\"duration_us\":"));
+        assert!(line.ends_with(",\"referer\":null,\"user_agent\":null}\n"));
+    }
+}
@@ -0,0 +1,312 @@
+use crate::{Context, Middleware, Request, Response, StatusCode};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+    fs,
+    io,
+    path::Path
+};
+
+/// The users of an htpasswd file, e.g. one made with `htpasswd -cB users.htpasswd alice`.
+///
+/// Only bcrypt hashes (`$2y$`, `$2b$` and `$2a$`) are accepted.
+/// The other formats htpasswd knows are either unsalted or fast enough to be guessed.
+#[derive(Clone, Debug, Default)]
+pub struct Htpasswd {
+    users: HashMap<String, String>,
+    // Checked for unknown users, so that they take as long as known ones and the response time doesn't give away who exists.
+    dummy: Option<String>
+}
+
+impl Htpasswd {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, HtpasswdError> {
+        let content = fs::read_to_string(path).map_err(HtpasswdError::Io)?;
+        Self::parse(&content)
+    }
+
+    /// Reads `user:hash` lines. Empty lines and lines starting with `#` are skipped.
+    pub fn parse(content: &str) -> Result<Self, HtpasswdError> {
+        let mut users = HashMap::new();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let number = index + 1;
+            let (user, hash) = line
+                .split_once(':')
+                .ok_or(HtpasswdError::Malformed { line: number })?;
+            if user.is_empty() {
+                return Err(HtpasswdError::Malformed { line: number });
+            }
+            if !["$2y$", "$2b$", "$2a$"].iter().any(|prefix| hash.starts_with(prefix)) {
+                return Err(HtpasswdError::UnsupportedHash { line: number });
+            }
+            users.insert(user.to_string(), hash.to_string());
+        }
+        // The dummy uses the highest cost of the file, because that's what the slowest known user costs.
+        let dummy = users
+            .values()
+            .filter_map(|hash| hash.get(4..6)?.parse().ok())
+            .max()
+            .and_then(|cost| bcrypt::hash("not a password", cost).ok());
+        Ok(Htpasswd { users, dummy })
+    }
+
+    /// Checks the password of `user`. Unknown users and broken hashes never match.
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        match (self.users.get(user), &self.dummy) {
+            (Some(hash), _) => bcrypt::verify(password, hash).unwrap_or(false),
+            (None, Some(dummy)) => {
+                let _ = bcrypt::verify(password, dummy);
+                false
+            }
+            (None, None) => false
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HtpasswdError {
+    Io(io::Error),
+    /// A line isn't made of a user name, a colon and a hash.
+    Malformed { line: usize },
+    UnsupportedHash { line: usize }
+}
+
+impl fmt::Display for HtpasswdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HtpasswdError::Io(error) => write!(f, "failed to read the htpasswd file: {error}"),
+            HtpasswdError::Malformed { line } => write!(f, "line {line} of the htpasswd file is malformed"),
+            HtpasswdError::UnsupportedHash { line } => write!(f, "line {line} of the htpasswd file doesn't use a bcrypt hash")
+        }
+    }
+}
+
+impl Error for HtpasswdError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HtpasswdError::Io(error) => Some(error),
+            _ => None
+        }
+    }
+}
+
+/// A middleware that only lets requests through with `Authorization: Basic` credentials from an htpasswd file.
+///
+/// Clients without valid credentials get `401 Unauthorized`, which makes browsers ask for a user name and password.
+/// The user is put into the `Context`, so e.g. the access log can show it.
+pub struct BasicAuth {
+    realm: String,
+    users: Htpasswd,
+    allowed: Option<HashSet<String>>
+}
+
+impl BasicAuth {
+    /// `realm` is shown to the user by most browsers, so they know which password to enter.
+    pub fn new(realm: impl Into<String>, users: Htpasswd) -> Self {
+        BasicAuth {
+            realm: realm.into(),
+            users,
+            allowed: None
+        }
+    }
+
+    /// Only lets these users in. Everyone else from the htpasswd file gets `403 Forbidden`.
+    pub fn with_allowed_users<I, S>(mut self, users: I) -> Self
+        where I: IntoIterator<Item = S>,
+              S: Into<String> {
+        self.allowed = Some(users.into_iter().map(Into::into).collect());
+        self
+    }
+
+    fn challenge(&self) -> Response {
+        let challenge = format!("Basic realm={}, charset=\"UTF-8\"", quote(&self.realm));
+        unauthorized(challenge)
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn before(&self, request: &Request, context: &mut Context) -> Option<Response> {
+        let Some((user, password)) = basic_credentials(request) else {
+            return Some(self.challenge());
+        };
+        if !self.users.verify(&user, &password) {
+            return Some(self.challenge());
+        }
+        if !is_allowed(&self.allowed, &user) {
+            return Some(forbidden());
+        }
+        context.set_user(user);
+        None
+    }
+}
+
+/// A middleware that only lets requests through with one of the configured `Authorization: Bearer` tokens.
+///
+/// Every token belongs to a name, which is put into the `Context` like a user name.
+pub struct BearerAuth {
+    realm: String,
+    tokens: Vec<(String, String)>,
+    allowed: Option<HashSet<String>>
+}
+
+impl BearerAuth {
+    pub fn new(realm: impl Into<String>) -> Self {
+        BearerAuth {
+            realm: realm.into(),
+            tokens: Vec::new(),
+            allowed: None
+        }
+    }
+
+    /// Accepts `token` and names whoever sends it `name`.
+    pub fn with_token(mut self, token: impl Into<String>, name: impl Into<String>) -> Self {
+        self.tokens.push((token.into(), name.into()));
+        self
+    }
+
+    /// Only lets the tokens with these names in. The other tokens get `403 Forbidden`.
+    pub fn with_allowed_users<I, S>(mut self, names: I) -> Self
+        where I: IntoIterator<Item = S>,
+              S: Into<String> {
+        self.allowed = Some(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    fn challenge(&self, error: Option<&str>) -> Response {
+        let mut challenge = format!("Bearer realm={}", quote(&self.realm));
+        if let Some(error) = error {
+            challenge.push_str(&format!(", error=\"{error}\""));
+        }
+        unauthorized(challenge)
+    }
+}
+
+impl Middleware for BearerAuth {
+    fn before(&self, request: &Request, context: &mut Context) -> Option<Response> {
+        let Some(token) = credentials(request, "Bearer") else {
+            // A client that didn't even try doesn't get an error code, as RFC 6750 asks.
+            return Some(self.challenge(None));
+        };
+        // Every token is compared, so the time it takes doesn't tell how many tokens there are or which one almost matched.
+        let name = self.tokens
+            .iter()
+            .fold(None, |found, (known, name)| if constant_time_eq(known.as_bytes(), token.as_bytes()) { Some(name) } else { found });
+        let Some(name) = name else {
+            return Some(self.challenge(Some("invalid_token")));
+        };
+        if !is_allowed(&self.allowed, name) {
+            return Some(forbidden());
+        }
+        context.set_user(name.clone());
+        None
+    }
+}
+
+// Returns what follows the scheme in the `Authorization` header. Scheme names are case-insensitive.
+fn credentials<'a>(request: &'a Request, scheme: &str) -> Option<&'a str> {
+    let (name, value) = request.header("Authorization")?.split_once(' ')?;
+    name.eq_ignore_ascii_case(scheme).then(|| value.trim())
+}
+
+fn basic_credentials(request: &Request) -> Option<(String, String)> {
+    let decoded = STANDARD.decode(credentials(request, "Basic")?).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    // User names can't contain a colon, but passwords can.
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+fn is_allowed(allowed: &Option<HashSet<String>>, user: &str) -> bool {
+    allowed.as_ref().is_none_or(|allowed| allowed.contains(user))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn unauthorized(challenge: String) -> Response {
+    Response::text(StatusCode::Unauthorized, "Unauthorized\n").with_header("WWW-Authenticate", challenge)
+}
+
+fn forbidden() -> Response {
+    Response::text(StatusCode::Forbidden, "Forbidden\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Handler, Params, Router};
+
+    fn router() -> Router {
+        let hash = bcrypt::hash_with_result("secret", 4)
+            .unwrap()
+            .format_for_version(bcrypt::Version::TwoY);
+        let users = Htpasswd::parse(&format!("# admins\nalice:{hash}\nbob:{hash}\n")).unwrap();
+        let mut router = Router::new();
+        router
+            .get("/", |_: &Request, _: &Params| Response::text(StatusCode::Ok, "public"))
+            .get("/admin/stats", |_: &Request, _: &Params| Response::text(StatusCode::Ok, "stats"))
+            .get("/api/posts", |_: &Request, _: &Params| Response::text(StatusCode::Ok, "posts"))
+            .wrap("/admin", BasicAuth::new("Admin area", users).with_allowed_users(["alice"]))
+            .wrap("/api", BearerAuth::new("api").with_token("t0ken", "deploy-bot"));
+        router
+    }
+
+    fn get(router: &Router, target: &str, authorization: Option<&str>) -> Response {
+        let header = authorization.map_or_else(String::new, |value| format!("Authorization: {value}\r\n"));
+        let request = Request::parse(&mut format!("GET {target} HTTP/1.1\r\n{header}\r\n").as_bytes()).unwrap();
+        router.handle(&request, &Params::default())
+    }
+
+    #[test]
+    fn checks_basic_credentials_on_a_prefix() {
+        let router = router();
+        let basic = |credentials: &str| format!("Basic {}", STANDARD.encode(credentials));
+
+        assert_eq!(StatusCode::Ok, get(&router, "/", None).status());
+        let response = get(&router, "/admin/stats", None);
+        assert_eq!(StatusCode::Unauthorized, response.status());
+        assert_eq!(Some("Basic realm=\"Admin area\", charset=\"UTF-8\""), response.headers().get("www-authenticate"));
+        assert_eq!(StatusCode::Unauthorized, get(&router, "/admin/stats", Some(&basic("alice:wrong"))).status());
+        assert_eq!(StatusCode::Forbidden, get(&router, "/admin/stats", Some(&basic("bob:secret"))).status());
+        assert_eq!(StatusCode::Ok, get(&router, "/admin/stats", Some(&basic("alice:secret"))).status());
+        // Routes that don't exist are hidden behind the login too.
+        assert_eq!(StatusCode::Unauthorized, get(&router, "/admin/nothing", None).status());
+    }
+
+    #[test]
+    fn checks_bearer_tokens() {
+        let router = router();
+
+        assert_eq!(Some("Bearer realm=\"api\""), get(&router, "/api/posts", None).headers().get("www-authenticate"));
+        let response = get(&router, "/api/posts", Some("Bearer nope"));
+        assert_eq!(StatusCode::Unauthorized, response.status());
+        assert_eq!(Some("Bearer realm=\"api\", error=\"invalid_token\""), response.headers().get("www-authenticate"));
+        assert_eq!(StatusCode::Ok, get(&router, "/api/posts", Some("bearer t0ken")).status());
+    }
+
+    #[test]
+    fn rejects_weak_htpasswd_hashes() {
+        assert!(matches!(Htpasswd::parse("alice:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g="), Err(HtpasswdError::UnsupportedHash { line: 1 })));
+        assert!(matches!(Htpasswd::parse("\nalice"), Err(HtpasswdError::Malformed { line: 2 })));
+    }
+
+    #[test]
+    fn checks_unknown_users_against_a_dummy_of_the_same_cost() {
+        let hash = bcrypt::hash("secret", 5).unwrap();
+        let users = Htpasswd::parse(&format!("alice:{hash}\n")).unwrap();
+        assert!(users.dummy.as_deref().is_some_and(|dummy| dummy.starts_with("$2b$05$")));
+        assert!(users.verify("alice", "secret"));
+        assert!(!users.verify("mallory", "secret"));
+        assert!(!users.verify("mallory", "not a password"));
+    }
+}
@@ -0,0 +1,213 @@
+use crate::{
+    request::{parse_header, read_line},
+    Headers,
+    Limits,
+    ParseError
+};
+use std::io::{self, prelude::*};
+
+// Writes are collected up to this size, so that writing a few bytes at a time doesn't turn into a flood of tiny chunks.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// Writes a response body in `Transfer-Encoding: chunked` format.
+///
+/// Every `flush` sends what has been written so far as one chunk, so the client sees the data right away.
+/// Writing more than the limit fails, because there's no way to take back a response that has already started.
+pub struct ChunkedWriter<'a> {
+    inner: &'a mut dyn Write,
+    buffer: Vec<u8>,
+    written: u64,
+    limit: u64,
+    trailers: Headers,
+    // Without encoding, the data is passed through as-is, which is used to collect a stream in memory.
+    encode: bool
+}
+
+impl<'a> ChunkedWriter<'a> {
+    pub(crate) fn new(inner: &'a mut dyn Write, limit: u64) -> Self {
+        ChunkedWriter {
+            inner,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+            written: 0,
+            limit,
+            trailers: Headers::new(),
+            encode: true
+        }
+    }
+
+    pub(crate) fn unencoded(inner: &'a mut dyn Write, limit: u64) -> Self {
+        ChunkedWriter {
+            encode: false,
+            ..ChunkedWriter::new(inner, limit)
+        }
+    }
+
+    /// Adds a field that is sent after the body, e.g. a checksum that is only known once everything was written.
+    pub fn trailer(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.trailers.append(name, value);
+    }
+
+    /// How many body bytes were written so far.
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    fn write_chunk(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        if !self.encode {
+            self.inner.write_all(&self.buffer)?;
+            self.buffer.clear();
+            return Ok(());
+        }
+        // An empty chunk would mark the end of the body, so this must never be called without data.
+        write!(self.inner, "{:x}\r\n", self.buffer.len())?;
+        self.inner.write_all(&self.buffer)?;
+        self.inner.write_all(b"\r\n")?;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Sends the last chunk and the trailers.
+    pub(crate) fn finish(mut self) -> io::Result<()> {
+        self.write_chunk()?;
+        if self.encode {
+            write!(self.inner, "0\r\n{}\r\n", self.trailers)?;
+        }
+        self.inner.flush()
+    }
+}
+
+impl Write for ChunkedWriter<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.written + data.len() as u64 > self.limit {
+            return Err(io::Error::other(format!("streamed body exceeds {} bytes", self.limit)));
+        }
+        self.buffer.extend_from_slice(data);
+        self.written += data.len() as u64;
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_chunk()?;
+        self.inner.flush()
+    }
+}
+
+/// Reads a chunked request body and the trailer fields after it.
+///
+/// The trailers count against the same limits as the header fields.
+pub(crate) fn read_chunked<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<(Vec<u8>, Headers), ParseError> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader)?.ok_or(ParseError::UnexpectedEof)?;
+        // Chunk extensions after a `;` are allowed, but nobody uses them, so they're skipped.
+        let size = line
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim();
+        // `from_str_radix` would also take a leading `+`, which another parser in front of the server might read differently.
+        if size.is_empty() || !size.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(ParseError::InvalidChunk);
+        }
+        let size = u64::from_str_radix(size, 16).map_err(|_| ParseError::InvalidChunk)?;
+        if size == 0 {
+            break;
+        }
+        // The size comes from the client, so adding it to the length could overflow.
+        if size > limits.max_body_size.saturating_sub(body.len() as u64) {
+            return Err(ParseError::PayloadTooLarge);
+        }
+        let read = reader
+            .take(size)
+            .read_to_end(&mut body)?;
+        if (read as u64) < size {
+            return Err(ParseError::UnexpectedEof);
+        }
+        if !read_line(reader)?.ok_or(ParseError::UnexpectedEof)?.is_empty() {
+            return Err(ParseError::InvalidChunk);
+        }
+    }
+
+    let mut trailers = Headers::new();
+    let mut trailer_size = 0;
+    loop {
+        let line = read_line(reader)?.ok_or(ParseError::UnexpectedEof)?;
+        if line.is_empty() {
+            break;
+        }
+        trailer_size += line.len();
+        if trailer_size > limits.max_header_size {
+            return Err(ParseError::HeadersTooLarge);
+        }
+        if trailers.len() >= limits.max_headers {
+            return Err(ParseError::TooManyHeaders);
+        }
+        let (name, value) = parse_header(&line)?;
+        trailers.append(name, value);
+    }
+    Ok((body, trailers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_chunks_and_trailers() {
+        let mut output = Vec::new();
+        let mut writer = ChunkedWriter::new(&mut output, 100);
+        writer.write_all(b"Hello, ").unwrap();
+        writer.flush().unwrap();
+        writer.write_all(b"world!").unwrap();
+        writer.trailer("Checksum", "abc");
+        writer.finish().unwrap();
+
+        assert_eq!("7\r\nHello, \r\n6\r\nworld!\r\n0\r\nChecksum: abc\r\n\r\n", String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn refuses_to_write_past_the_limit() {
+        let mut output = Vec::new();
+        let mut writer = ChunkedWriter::new(&mut output, 4);
+        assert!(writer.write_all(b"12345").is_err());
+    }
+
+    fn limits(max_body_size: u64) -> Limits {
+        Limits {
+            max_body_size,
+            max_headers: 2,
+            max_header_size: 64
+        }
+    }
+
+    #[test]
+    fn reads_chunks_and_trailers() {
+        let mut raw = "5;name=value\r\nHello\r\n7\r\n, world\r\n0\r\nExpires: never\r\n\r\nnext".as_bytes();
+        let (body, trailers) = read_chunked(&mut raw, &limits(100)).unwrap();
+
+        assert_eq!(b"Hello, world", &body[..]);
+        assert_eq!(Some("never"), trailers.get("expires"));
+        assert_eq!(b"next", raw);
+        assert!(matches!(read_chunked(&mut "5\r\nHello\r\n0\r\n\r\n".as_bytes(), &limits(4)), Err(ParseError::PayloadTooLarge)));
+        assert!(matches!(read_chunked(&mut "zz\r\n".as_bytes(), &limits(4)), Err(ParseError::InvalidChunk)));
+        assert!(matches!(read_chunked(&mut "+5\r\nHello\r\n0\r\n\r\n".as_bytes(), &limits(100)), Err(ParseError::InvalidChunk)));
+    }
+
+    #[test]
+    fn rejects_huge_chunks_and_too_many_trailers() {
+        // Added to what was already read, the largest possible size would wrap around to a small number.
+        let mut raw = "1\r\na\r\nffffffffffffffff\r\naaaa".as_bytes();
+        assert!(matches!(read_chunked(&mut raw, &limits(100)), Err(ParseError::PayloadTooLarge)));
+
+        let mut raw = "0\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n".as_bytes();
+        assert!(matches!(read_chunked(&mut raw, &limits(100)), Err(ParseError::TooManyHeaders)));
+        let long = format!("0\r\nA: {}\r\n\r\n", "a".repeat(100));
+        assert!(matches!(read_chunked(&mut long.as_bytes(), &limits(100)), Err(ParseError::HeadersTooLarge)));
+    }
+}
@@ -0,0 +1,361 @@
+use crate::{Body, ChunkedWriter, ConnectionOptions, Context, Middleware, Request, Response, StatusCode};
+use flate2::{
+    write::{GzEncoder, ZlibEncoder},
+    Compression as Level
+};
+use std::{
+    io::{self, prelude::*},
+    mem
+};
+
+// Types that are already compressed, like images and videos, only get bigger when they're compressed again.
+const COMPRESSIBLE_TYPES: [&str; 6] = [
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/wasm",
+    "application/manifest+json",
+    "image/svg+xml"
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Coding {
+    Gzip,
+    Deflate
+}
+
+impl Coding {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Coding::Gzip => "gzip",
+            Coding::Deflate => "deflate"
+        }
+    }
+}
+
+/// Returns the quality the client gave `coding` in its `Accept-Encoding` header, or `None` if it doesn't want it.
+fn quality(request: &Request, coding: &str) -> Option<f32> {
+    let mut wildcard = None;
+    for entry in request
+        .headers()
+        .get_all("Accept-Encoding")
+        .flat_map(|value| value.split(',')) {
+        let mut parts = entry.split(';').map(str::trim);
+        let name = parts.next().unwrap_or_default();
+        // A missing `q` means 1, and one that can't be read means the client didn't say anything useful, so it's not used.
+        let q = parts
+            .find_map(|parameter| parameter.strip_prefix("q="))
+            .map_or(Some(1.0), |q| q.parse::<f32>().ok())
+            .unwrap_or(0.0);
+        // `x-gzip` is an old name for gzip, which clients are still allowed to send.
+        if name.eq_ignore_ascii_case(coding) || (coding == "gzip" && name.eq_ignore_ascii_case("x-gzip")) {
+            return (q > 0.0).then_some(q);
+        }
+        if name == "*" {
+            wildcard = Some(q);
+        }
+    }
+    wildcard.filter(|q| *q > 0.0)
+}
+
+/// Whether the client accepts a gzip body, e.g. a precompressed file.
+pub(crate) fn accepts_gzip(request: &Request) -> bool {
+    quality(request, "gzip").is_some()
+}
+
+// Picks the coding the client likes best. gzip wins a tie, because it's the one every client knows.
+fn negotiate(request: &Request) -> Option<Coding> {
+    let gzip = quality(request, "gzip");
+    let deflate = quality(request, "deflate");
+    match (gzip, deflate) {
+        (Some(gzip), Some(deflate)) if deflate > gzip => Some(Coding::Deflate),
+        (Some(_), _) => Some(Coding::Gzip),
+        (None, Some(_)) => Some(Coding::Deflate),
+        (None, None) => None
+    }
+}
+
+fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    essence.starts_with("text/") || COMPRESSIBLE_TYPES.contains(&essence.as_str())
+}
+
+/// Adds `Accept-Encoding` to the `Vary` header, so caches keep the compressed and the uncompressed response apart.
+pub(crate) fn vary_on_encoding(response: &mut Response) {
+    let headers = response.headers_mut();
+    if headers.has_token("Vary", "Accept-Encoding") || headers.has_token("Vary", "*") {
+        return;
+    }
+    headers.append("Vary", "Accept-Encoding");
+}
+
+enum Encoder<W: Write> {
+    Gzip(GzEncoder<W>),
+    Deflate(ZlibEncoder<W>)
+}
+
+impl<W: Write> Encoder<W> {
+    fn new(coding: Coding, writer: W, level: Level) -> Self {
+        match coding {
+            Coding::Gzip => Encoder::Gzip(GzEncoder::new(writer, level)),
+            // HTTP's `deflate` is the zlib format, not a raw deflate stream.
+            Coding::Deflate => Encoder::Deflate(ZlibEncoder::new(writer, level))
+        }
+    }
+
+    fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Deflate(encoder) => encoder.finish()
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.write(data),
+            Encoder::Deflate(encoder) => encoder.write(data)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Deflate(encoder) => encoder.flush()
+        }
+    }
+}
+
+/// A middleware that compresses response bodies with gzip or deflate, whichever the client prefers in `Accept-Encoding`.
+///
+/// Only text and a few other formats that shrink well are compressed, and only if the body is at least `min_size` bytes,
+/// because for tiny bodies the gzip header takes more than compression saves.
+/// Bodies that aren't in memory, like files and streams, are compressed while they're sent, which makes them chunked.
+///
+/// Responses that already have a `Content-Encoding`, e.g. a precompressed file, are left alone.
+/// So are files that could come out larger than `max_size` after compression, because a chunked body that grows past the connection's `max_streamed_body` is cut off.
+pub struct Compression {
+    min_size: u64,
+    max_size: u64,
+    level: Level
+}
+
+impl Compression {
+    pub fn new() -> Self {
+        Compression {
+            min_size: 1024,
+            max_size: ConnectionOptions::default().max_streamed_body,
+            level: Level::default()
+        }
+    }
+
+    /// Bodies smaller than this are sent as they are. The default is 1024 bytes.
+    pub fn with_min_size(mut self, min_size: u64) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Files and other bodies that are read while they're sent are only compressed, if the result is sure to fit into `max_size` bytes.
+    /// This should be the `max_streamed_body` of the server's `ConnectionOptions`, which is also the default.
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Trades speed for size, from 0 (no compression) to 9 (smallest). The default is 6.
+    pub fn with_level(mut self, level: u32) -> Self {
+        self.level = Level::new(level.min(9));
+        self
+    }
+
+    fn compress(&self, coding: Coding, body: Body) -> Body {
+        let level = self.level;
+        match body {
+            Body::Empty | Body::Bytes(_) | Body::Text(_) => {
+                let bytes = body.as_bytes().unwrap_or_default();
+                let mut encoder = Encoder::new(coding, Vec::with_capacity(bytes.len() / 2), level);
+                // Writing into a `Vec` can't fail, so the body is only kept as it is in theory.
+                match encoder.write_all(bytes).and_then(|()| encoder.finish()) {
+                    Ok(compressed) => Body::Bytes(compressed),
+                    Err(_) => body
+                }
+            }
+            Body::File { file, length } => compress_reader(coding, level, Box::new(file.take(length))),
+            Body::Reader { reader, length } => compress_reader(coding, level, Box::new(reader.take(length))),
+            Body::Stream(f) => Body::stream(move |writer| {
+                let mut encoder = Encoder::new(coding, &mut *writer, level);
+                // The handler writes into a writer that doesn't add chunk sizes, so that only the compressed data gets chunked.
+                let mut plain = ChunkedWriter::unencoded(&mut encoder, u64::MAX);
+                f(&mut plain)?;
+                plain.finish()?;
+                encoder.finish()?;
+                Ok(())
+            })
+        }
+    }
+}
+
+// Data that doesn't compress at all is stored in blocks of up to 64 KiB, which adds a few bytes per block and some for the header and the trailer.
+// This is well above zlib's own `compressBound`, so the compressed body never gets larger than that.
+fn worst_case_size(length: u64) -> u64 {
+    length.saturating_add(length / 1024).saturating_add(64)
+}
+
+fn compress_reader(coding: Coding, level: Level, mut reader: Box<dyn Read + Send>) -> Body {
+    Body::stream(move |writer| {
+        let mut encoder = Encoder::new(coding, writer, level);
+        io::copy(&mut reader, &mut encoder)?;
+        encoder.finish()?;
+        Ok(())
+    })
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Middleware for Compression {
+    fn after(&self, request: &Request, mut response: Response, _context: &mut Context) -> Response {
+        let compressible = response
+            .headers()
+            .get("Content-Type")
+            .is_some_and(is_compressible);
+        if !compressible || response.headers().contains("Content-Encoding") {
+            return response;
+        }
+        // The response depends on `Accept-Encoding` even when it isn't compressed this time.
+        vary_on_encoding(&mut response);
+        let status = response.status();
+        if !status.allows_body() || status == StatusCode::PartialContent {
+            return response;
+        }
+        if response.body().len().is_some_and(|length| length < self.min_size) {
+            return response;
+        }
+        if let Body::File { length, .. } | Body::Reader { length, .. } = response.body() {
+            if worst_case_size(*length) > self.max_size {
+                return response;
+            }
+        }
+        let Some(coding) = negotiate(request) else {
+            return response;
+        };
+
+        let body = mem::take(response.body_mut());
+        *response.body_mut() = self.compress(coding, body);
+        let headers = response.headers_mut();
+        headers.insert("Content-Encoding", coding.as_str());
+        // Ranges would refer to the compressed bytes, which differ with every compression level, so they're not offered.
+        headers.remove("Accept-Ranges");
+        // The compressed body isn't byte for byte the same as the file anymore, so its entity tag can only be a weak one.
+        if let Some(etag) = headers.get("ETag").filter(|etag| !etag.starts_with("W/")) {
+            let weak = format!("W/{etag}");
+            headers.insert("ETag", weak);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Chain, Handler, Params};
+    use flate2::read::{GzDecoder, ZlibDecoder};
+
+    fn request(accept_encoding: &str) -> Request {
+        let raw = format!("GET / HTTP/1.1\r\nAccept-Encoding: {accept_encoding}\r\n\r\n");
+        Request::parse(&mut raw.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn negotiates_with_q_values() {
+        assert_eq!(Some(Coding::Gzip), negotiate(&request("gzip, deflate")));
+        assert_eq!(Some(Coding::Deflate), negotiate(&request("gzip;q=0.5, deflate")));
+        assert_eq!(Some(Coding::Deflate), negotiate(&request("gzip;q=0, *")));
+        assert_eq!(Some(Coding::Gzip), negotiate(&request("x-gzip")));
+        assert_eq!(None, negotiate(&request("br, identity")));
+        assert_eq!(None, negotiate(&request("*;q=0")));
+    }
+
+    #[test]
+    fn compresses_large_text_bodies() {
+        let text = "hello, compression! ".repeat(100);
+        let expected = text.clone();
+        let chain = Chain::new(move |_: &Request, _: &Params| {
+            Response::text(StatusCode::Ok, text.as_str()).with_header("ETag", "\"v1\"")
+        })
+            .with_middleware(Compression::new());
+
+        let response = chain.handle(&request("deflate;q=0.9, gzip"), &Params::default());
+        assert_eq!(Some("gzip"), response.headers().get("content-encoding"));
+        assert_eq!(Some("Accept-Encoding"), response.headers().get("vary"));
+        assert_eq!(Some("W/\"v1\""), response.headers().get("etag"));
+        let compressed = response.body().as_bytes().unwrap();
+        assert!(compressed.len() < expected.len() / 10);
+        let mut decompressed = String::new();
+        GzDecoder::new(compressed).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(expected, decompressed);
+
+        let response = chain.handle(&request("deflate"), &Params::default());
+        let mut decompressed = String::new();
+        ZlibDecoder::new(response.body().as_bytes().unwrap()).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(expected, decompressed);
+    }
+
+    #[test]
+    fn leaves_files_alone_that_could_outgrow_the_stream_limit() {
+        const LIMIT: u64 = 64 * 1024;
+        // Letters that never repeat in a pattern compress about as badly as text can.
+        let text: Vec<u8> = (0..LIMIT).map(|i| b'a' + (i * 7919 % 26) as u8).collect();
+        let chain = Chain::new(move |request: &Request, _: &Params| {
+            let length = request.query("length").unwrap().parse().unwrap();
+            Response::new(StatusCode::Ok)
+                .with_header("Content-Type", "text/plain")
+                .with_body(Body::Reader { reader: Box::new(io::Cursor::new(text.clone())), length })
+        })
+            .with_middleware(Compression::new().with_max_size(LIMIT));
+        let get = |length: u64| {
+            let raw = format!("GET /?length={length} HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+            chain.handle(&Request::parse(&mut raw.as_bytes()).unwrap(), &Params::default())
+        };
+
+        // Compressed, a body of exactly the limit might not fit anymore, so it's sent as it is.
+        let response = get(LIMIT);
+        assert_eq!(None, response.headers().get("content-encoding"));
+        assert_eq!(Some(LIMIT), response.body().len());
+
+        let response = get(LIMIT - LIMIT / 512);
+        assert_eq!(Some("gzip"), response.headers().get("content-encoding"));
+        response.write_with_limit(&mut Vec::new(), false, LIMIT).unwrap();
+    }
+
+    #[test]
+    fn leaves_small_and_binary_bodies_alone() {
+        let chain = Chain::new(|request: &Request, _: &Params| match request.query("kind") {
+            Some("png") => Response::new(StatusCode::Ok)
+                .with_header("Content-Type", "image/png")
+                .with_body(vec![0; 4096]),
+            _ => Response::text(StatusCode::Ok, "tiny")
+        })
+            .with_middleware(Compression::new());
+        let get = |target: &str| {
+            let raw = format!("GET {target} HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+            chain.handle(&Request::parse(&mut raw.as_bytes()).unwrap(), &Params::default())
+        };
+
+        let tiny = get("/");
+        assert_eq!(None, tiny.headers().get("content-encoding"));
+        assert_eq!(Some("Accept-Encoding"), tiny.headers().get("vary"));
+        let image = get("/?kind=png");
+        assert_eq!(None, image.headers().get("content-encoding"));
+        assert_eq!(None, image.headers().get("vary"));
+    }
+}
@@ -0,0 +1,647 @@
+use crate::{virtual_host::is_host_pattern, ConnectionOptions, LogFormat};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt,
+    fs,
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration
+};
+
+/// What `hello_http --help` prints.
+pub const USAGE: &str = "\
+Usage: hello_http [OPTIONS] [ROOT]
+
+Serves the files in ROOT (default: hello_http/public) over HTTP.
+
+Options:
+    --config FILE           Read the configuration from a TOML file; the flags below override it
+    --root DIR              Serve the files in DIR, like ROOT
+    --listen ADDR           Answer HTTP on ADDR, e.g. 127.0.0.1:7878 (repeatable)
+    --https ADDR            Answer HTTPS on ADDR (repeatable, needs the `tls` feature)
+    --tls-cert FILE         The certificate chain for HTTPS, as PEM
+    --tls-key FILE          The private key for HTTPS, as PEM
+    --workers N             Keep N workers running
+    --max-workers N         Start up to N workers during bursts
+    --queue-capacity N      Turn connections away once N are waiting for a worker
+    --idle-timeout SECS     Close connections that don't send a request for SECS seconds
+    --header-timeout SECS   Give clients SECS seconds to send the request head
+    --body-timeout SECS     Give clients SECS seconds to send the request body
+    --write-timeout SECS    Give up on clients that don't read for SECS seconds
+    --grace-period SECS     Let running requests finish for SECS seconds after a shutdown started
+    --log-format FORMAT     Log requests as `common`, `combined` or `json`
+    --log-file FILE         Append the access log to FILE instead of stderr
+    --mount PREFIX=DIR      Also serve the files in DIR below PREFIX, e.g. /docs=./docs (repeatable)
+    --host NAME=DIR         Serve the files in DIR to requests for NAME, e.g. *.example.com=./sites/example (repeatable)
+    --check-config          Check the configuration and exit
+    -h, --help              Show this help
+";
+
+/// A directory that is served below a path prefix, e.g. `/docs` from `./site/docs`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mount {
+    pub prefix: String,
+    pub directory: PathBuf,
+    /// Lists the contents of directories without an index file.
+    pub listing: bool
+}
+
+/// A site that is served to requests for its names, instead of the root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VirtualHost {
+    /// Host names like `example.com`, or wildcards like `*.example.com` that match every subdomain.
+    pub names: Vec<String>,
+    pub root: PathBuf,
+    /// Lists the contents of directories without an index file.
+    pub listing: bool
+}
+
+/// The certificate chain and the private key for HTTPS, as PEM files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsFiles {
+    pub certificate: PathBuf,
+    pub key: PathBuf
+}
+
+/// Everything the `hello_http` binary can be configured with.
+///
+/// The values come from the defaults, then from a TOML file, then from command-line flags, see `Command::from_args`.
+/// Relative paths in a file are relative to the file, and relative paths in flags are relative to the working directory.
+///
+/// ```toml
+/// root = "public"
+///
+/// [listen]
+/// http = ["127.0.0.1:7878", "[::1]:7878"]
+///
+/// [pool]
+/// workers = 4
+/// max_workers = 16
+///
+/// [timeouts]
+/// idle = 5
+/// header = 2.5
+///
+/// [log]
+/// format = "combined"
+///
+/// [[mount]]
+/// prefix = "/docs"
+/// directory = "docs"
+/// listing = true
+///
+/// [[host]]
+/// names = ["wiki.internal", "*.wiki.internal"]
+/// root = "sites/wiki"
+/// ```
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The directory that is served for every path no mount claims, to requests for hosts that aren't in `hosts`.
+    pub root: PathBuf,
+    pub listen: Vec<SocketAddr>,
+    pub https: Vec<SocketAddr>,
+    pub tls: Option<TlsFiles>,
+    pub workers: usize,
+    pub max_workers: usize,
+    pub queue_capacity: usize,
+    /// The timeouts and limits of every connection.
+    pub connection: ConnectionOptions,
+    pub grace_period: Duration,
+    pub log_format: LogFormat,
+    /// Where the access log goes. `None` means stderr.
+    pub log_file: Option<PathBuf>,
+    pub mounts: Vec<Mount>,
+    pub hosts: Vec<VirtualHost>
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            root: PathBuf::from("hello_http/public"),
+            listen: vec![SocketAddr::from(([127, 0, 0, 1], 7878))],
+            https: Vec::new(),
+            tls: None,
+            workers: 4,
+            max_workers: 16,
+            queue_capacity: 64,
+            connection: ConnectionOptions::default(),
+            grace_period: Duration::from_secs(10),
+            log_format: LogFormat::Common,
+            log_file: None,
+            mounts: Vec::new(),
+            hosts: Vec::new()
+        }
+    }
+}
+
+// The layout of the TOML file. Everything is optional, so a file only needs to contain what differs from the defaults.
+// Unknown keys are errors, because a misspelled key that's silently ignored is worse than one that stops the server from starting.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    root: Option<PathBuf>,
+    listen: Option<ListenSection>,
+    tls: Option<TlsSection>,
+    pool: Option<PoolSection>,
+    timeouts: Option<TimeoutsSection>,
+    log: Option<LogSection>,
+    #[serde(default, rename = "mount")]
+    mounts: Vec<MountSection>,
+    #[serde(default, rename = "host")]
+    hosts: Vec<HostSection>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ListenSection {
+    http: Option<Vec<String>>,
+    https: Option<Vec<String>>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TlsSection {
+    certificate: PathBuf,
+    key: PathBuf
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PoolSection {
+    workers: Option<usize>,
+    max_workers: Option<usize>,
+    queue_capacity: Option<usize>
+}
+
+// All timeouts are in seconds, and may have a fraction.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TimeoutsSection {
+    idle: Option<f64>,
+    header: Option<f64>,
+    body: Option<f64>,
+    write: Option<f64>,
+    grace_period: Option<f64>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LogSection {
+    format: Option<String>,
+    file: Option<PathBuf>
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MountSection {
+    prefix: String,
+    directory: PathBuf,
+    #[serde(default)]
+    listing: bool
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HostSection {
+    names: Vec<String>,
+    root: PathBuf,
+    #[serde(default)]
+    listing: bool
+}
+
+impl Config {
+    /// Reads a TOML file on top of the defaults.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path).map_err(|error| ConfigError::Io { path: path.to_path_buf(), error })?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        Self::parse(&content, base)
+    }
+
+    /// Reads a TOML document on top of the defaults. Relative paths in it are taken to be relative to `base`.
+    pub fn parse(content: &str, base: &Path) -> Result<Self, ConfigError> {
+        let file: File = toml::from_str(content).map_err(ConfigError::Toml)?;
+        let mut config = Config::default();
+        let mut problems = Vec::new();
+        let path = |path: PathBuf| if path.is_absolute() { path } else { base.join(path) };
+
+        if let Some(root) = file.root {
+            config.root = path(root);
+        }
+        if let Some(listen) = file.listen {
+            if let Some(http) = listen.http {
+                config.listen = addresses("listen.http", &http, &mut problems);
+            }
+            if let Some(https) = listen.https {
+                config.https = addresses("listen.https", &https, &mut problems);
+            }
+        }
+        if let Some(tls) = file.tls {
+            config.tls = Some(TlsFiles {
+                certificate: path(tls.certificate),
+                key: path(tls.key)
+            });
+        }
+        if let Some(pool) = file.pool {
+            config.workers = pool.workers.unwrap_or(config.workers);
+            config.max_workers = pool.max_workers.unwrap_or(config.max_workers);
+            config.queue_capacity = pool.queue_capacity.unwrap_or(config.queue_capacity);
+        }
+        if let Some(timeouts) = file.timeouts {
+            let options = &mut config.connection;
+            for (name, value, target) in [
+                ("timeouts.idle", timeouts.idle, &mut options.idle_timeout),
+                ("timeouts.header", timeouts.header, &mut options.header_timeout),
+                ("timeouts.body", timeouts.body, &mut options.body_timeout),
+                ("timeouts.write", timeouts.write, &mut options.write_timeout),
+                ("timeouts.grace_period", timeouts.grace_period, &mut config.grace_period)
+            ] {
+                if let Some(value) = value {
+                    match seconds(value) {
+                        Ok(duration) => *target = duration,
+                        Err(problem) => problems.push(format!("{name} {problem}"))
+                    }
+                }
+            }
+        }
+        if let Some(log) = file.log {
+            if let Some(format) = log.format {
+                match format.parse() {
+                    Ok(format) => config.log_format = format,
+                    Err(problem) => problems.push(format!("log.format: {problem}"))
+                }
+            }
+            config.log_file = log.file.map(path);
+        }
+        config.mounts = file.mounts
+            .into_iter()
+            .map(|mount| Mount {
+                prefix: mount.prefix,
+                directory: path(mount.directory),
+                listing: mount.listing
+            })
+            .collect();
+        config.hosts = file.hosts
+            .into_iter()
+            .map(|host| VirtualHost {
+                names: host.names,
+                root: path(host.root),
+                listing: host.listing
+            })
+            .collect();
+
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+
+    /// Checks everything that can be checked before the server starts, and lists all the problems at once.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+        if !self.root.is_dir() {
+            problems.push(format!("the root {} is not a directory", self.root.display()));
+        }
+
+        if self.listen.is_empty() && self.https.is_empty() {
+            problems.push(String::from("there is no address to listen on"));
+        }
+        let mut addresses = HashSet::new();
+        for address in self.listen.iter().chain(&self.https) {
+            if !addresses.insert(address) {
+                problems.push(format!("{address} is listed more than once"));
+            }
+        }
+        if !self.https.is_empty() {
+            if cfg!(not(feature = "tls")) {
+                problems.push(String::from("HTTPS needs hello_http to be built with `--features tls`"));
+            }
+            if self.tls.is_none() {
+                problems.push(String::from("HTTPS needs a certificate and a key in [tls] or in --tls-cert and --tls-key"));
+            }
+        }
+        if let Some(tls) = &self.tls {
+            for file in [&tls.certificate, &tls.key] {
+                if !file.is_file() {
+                    problems.push(format!("the TLS file {} doesn't exist", file.display()));
+                }
+            }
+        }
+
+        if self.workers == 0 {
+            problems.push(String::from("at least 1 worker is needed"));
+        }
+        if self.max_workers < self.workers {
+            problems.push(format!("max_workers ({}) can't be less than workers ({})", self.max_workers, self.workers));
+        }
+        if self.queue_capacity == 0 {
+            problems.push(String::from("the queue capacity has to be at least 1"));
+        }
+
+        let mut prefixes = HashSet::new();
+        for mount in &self.mounts {
+            let prefix = &mount.prefix;
+            // The prefix becomes part of a route pattern, so it can't contain parameters of its own.
+            let segments_are_literal = prefix
+                .split('/')
+                .skip(1)
+                .all(|segment| !segment.is_empty() && !segment.starts_with([':', '*']));
+            if !prefix.starts_with('/') || !segments_are_literal {
+                problems.push(format!("the mount prefix `{prefix}` has to look like `/docs` or `/static/images`"));
+            }
+            if !prefixes.insert(prefix) {
+                problems.push(format!("the mount prefix `{prefix}` is used more than once"));
+            }
+            if !mount.directory.is_dir() {
+                problems.push(format!("the mounted directory {} is not a directory", mount.directory.display()));
+            }
+        }
+
+        let mut names = HashSet::new();
+        for host in &self.hosts {
+            if host.names.is_empty() {
+                problems.push(format!("the site in {} has no host names", host.root.display()));
+            }
+            for name in &host.names {
+                if !is_host_pattern(name) {
+                    problems.push(format!("the host `{name}` has to look like `example.com` or `*.example.com`"));
+                } else if !names.insert(name.to_ascii_lowercase()) {
+                    problems.push(format!("the host `{name}` is used more than once"));
+                }
+            }
+            if !host.root.is_dir() {
+                problems.push(format!("the root {} of {} is not a directory", host.root.display(), host.names.join(", ")));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+}
+
+fn addresses(key: &str, values: &[String], problems: &mut Vec<String>) -> Vec<SocketAddr> {
+    values
+        .iter()
+        .filter_map(|value| match value.parse() {
+            Ok(address) => Some(address),
+            Err(_) => {
+                problems.push(format!("{key}: `{value}` isn't an address like 127.0.0.1:7878 or [::1]:7878"));
+                None
+            }
+        })
+        .collect()
+}
+
+fn seconds(value: f64) -> Result<Duration, String> {
+    // `Duration` can't be negative or infinite, and a timeout of 0 would turn the timeout off.
+    if value.is_finite() && value > 0.0 && value <= u32::MAX.into() {
+        Ok(Duration::from_secs_f64(value))
+    } else {
+        Err(format!("has to be a positive number of seconds, not {value}"))
+    }
+}
+
+// The flags that take a value.
+const FLAGS: [&str; 18] = [
+    "--config", "--root", "--listen", "--https", "--tls-cert", "--tls-key", "--workers", "--max-workers", "--queue-capacity",
+    "--idle-timeout", "--header-timeout", "--body-timeout", "--write-timeout", "--grace-period",
+    "--log-format", "--log-file", "--mount", "--host"
+];
+
+/// What the command line asks the binary to do.
+#[derive(Debug)]
+pub enum Command {
+    Serve(Config),
+    /// Validate the configuration and exit, e.g. before restarting a server with a changed file.
+    CheckConfig(Config),
+    Help
+}
+
+impl Command {
+    /// Reads the arguments after the program name.
+    /// The file given with `--config` is read first, no matter where the flag is, and every other flag overrides what it set.
+    pub fn from_args<I>(args: I) -> Result<Self, ConfigError>
+        where I: IntoIterator<Item = String> {
+        let mut flags = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+                _ => (arg, None)
+            };
+            match flag.as_str() {
+                "-h" | "--help" => return Ok(Command::Help),
+                "--check-config" => flags.push((flag, String::new())),
+                _ if flag.starts_with('-') => {
+                    if !FLAGS.contains(&flag.as_str()) {
+                        return Err(ConfigError::Usage(format!("unknown option {flag}")));
+                    }
+                    let value = inline
+                        .or_else(|| args.next())
+                        .ok_or_else(|| ConfigError::Usage(format!("{flag} needs a value")))?;
+                    flags.push((flag, value));
+                }
+                // For compatibility, the root can still be given without a flag.
+                _ => flags.push((String::from("--root"), flag))
+            }
+        }
+
+        let mut config = match flags.iter().rfind(|(flag, _)| flag == "--config") {
+            Some((_, path)) => Config::load(path)?,
+            None => Config::default()
+        };
+        // Flags that can be repeated replace the file's list the first time they're used, and add to it after that.
+        let mut replaced_listen = false;
+        let mut replaced_https = false;
+        let mut check = false;
+        for (flag, value) in flags {
+            let usage = |problem: String| ConfigError::Usage(format!("{flag}: {problem}"));
+            let count = || value.parse::<usize>().map_err(|_| usage(format!("`{value}` isn't a number")));
+            let duration = || value
+                .parse::<f64>()
+                .map_err(|_| format!("`{value}` isn't a number of seconds"))
+                .and_then(seconds)
+                .map_err(usage);
+            match flag.as_str() {
+                "--config" => {}
+                "--check-config" => check = true,
+                "--root" => config.root = PathBuf::from(&value),
+                "--listen" | "--https" => {
+                    let address = value
+                        .parse()
+                        .map_err(|_| usage(format!("`{value}` isn't an address like 127.0.0.1:7878")))?;
+                    let (list, replaced) = if flag == "--listen" {
+                        (&mut config.listen, &mut replaced_listen)
+                    } else {
+                        (&mut config.https, &mut replaced_https)
+                    };
+                    if !*replaced {
+                        list.clear();
+                        *replaced = true;
+                    }
+                    list.push(address);
+                }
+                "--tls-cert" | "--tls-key" => {
+                    let tls = config.tls.get_or_insert_with(|| TlsFiles {
+                        certificate: PathBuf::new(),
+                        key: PathBuf::new()
+                    });
+                    if flag == "--tls-cert" {
+                        tls.certificate = PathBuf::from(&value);
+                    } else {
+                        tls.key = PathBuf::from(&value);
+                    }
+                }
+                "--workers" => config.workers = count()?,
+                "--max-workers" => config.max_workers = count()?,
+                "--queue-capacity" => config.queue_capacity = count()?,
+                "--idle-timeout" => config.connection.idle_timeout = duration()?,
+                "--header-timeout" => config.connection.header_timeout = duration()?,
+                "--body-timeout" => config.connection.body_timeout = duration()?,
+                "--write-timeout" => config.connection.write_timeout = duration()?,
+                "--grace-period" => config.grace_period = duration()?,
+                "--log-format" => config.log_format = value.parse().map_err(usage)?,
+                "--log-file" => config.log_file = Some(PathBuf::from(&value)),
+                "--mount" => {
+                    let (prefix, directory) = value
+                        .split_once('=')
+                        .ok_or_else(|| usage(format!("`{value}` should look like /docs=./docs")))?;
+                    config.mounts.push(Mount {
+                        prefix: prefix.to_string(),
+                        directory: PathBuf::from(directory),
+                        listing: false
+                    });
+                }
+                "--host" => {
+                    let (name, root) = value
+                        .split_once('=')
+                        .ok_or_else(|| usage(format!("`{value}` should look like example.com=./sites/example")))?;
+                    config.hosts.push(VirtualHost {
+                        names: vec![name.to_string()],
+                        root: PathBuf::from(root),
+                        listing: false
+                    });
+                }
+                _ => unreachable!("{flag} is in FLAGS")
+            }
+        }
+
+        if check {
+            Ok(Command::CheckConfig(config))
+        } else {
+            Ok(Command::Serve(config))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io { path: PathBuf, error: io::Error },
+    /// The file isn't valid TOML, or has keys or values of the wrong type.
+    Toml(toml::de::Error),
+    /// The command line couldn't be understood.
+    Usage(String),
+    /// The values don't make sense, e.g. a timeout of 0 or a root that doesn't exist.
+    Invalid(Vec<String>)
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, error } => write!(f, "can't read {}: {error}", path.display()),
+            ConfigError::Toml(error) => write!(f, "the config file is malformed: {error}"),
+            ConfigError::Usage(problem) => write!(f, "{problem}"),
+            ConfigError::Invalid(problems) => {
+                write!(f, "the configuration is invalid:")?;
+                for problem in problems {
+                    write!(f, "\n  - {problem}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io { error, .. } => Some(error),
+            ConfigError::Toml(error) => Some(error),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn reads_a_file_and_lets_flags_override_it() {
+        let config = Config::parse(
+            "root = 'site'\n\
+             [listen]\nhttp = ['0.0.0.0:80', '[::]:80']\n\
+             [pool]\nworkers = 2\n\
+             [timeouts]\nheader = 2.5\n\
+             [log]\nformat = 'json'\n\
+             [[mount]]\nprefix = '/docs'\ndirectory = '/srv/docs'\nlisting = true\n",
+            Path::new("/etc/hello_http")
+        ).unwrap();
+        assert_eq!(PathBuf::from("/etc/hello_http/site"), config.root);
+        assert_eq!(2, config.listen.len());
+        assert_eq!(2, config.workers);
+        assert_eq!(16, config.max_workers);
+        assert_eq!(Duration::from_millis(2500), config.connection.header_timeout);
+        assert_eq!(Duration::from_secs(5), config.connection.idle_timeout);
+        assert_eq!(LogFormat::Json, config.log_format);
+        assert_eq!(PathBuf::from("/srv/docs"), config.mounts[0].directory);
+
+        let Command::CheckConfig(config) = Command::from_args(args("./public --workers=8 --listen 127.0.0.1:8080 --check-config")).unwrap() else {
+            panic!("--check-config should only check the configuration");
+        };
+        assert_eq!(PathBuf::from("./public"), config.root);
+        assert_eq!(8, config.workers);
+        assert_eq!(vec![SocketAddr::from(([127, 0, 0, 1], 8080))], config.listen);
+        assert!(matches!(Command::from_args(args("--workers many")), Err(ConfigError::Usage(_))));
+        assert!(matches!(Command::from_args(args("--wrokers 8")), Err(ConfigError::Usage(problem)) if problem.contains("unknown")));
+        assert!(matches!(Command::from_args(args("--help")), Ok(Command::Help)));
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        assert!(matches!(Config::parse("[pool]\nworkerz = 2\n", Path::new("")), Err(ConfigError::Toml(_))));
+        let Err(ConfigError::Invalid(problems)) = Config::parse("[listen]\nhttp = ['localhost']\n[timeouts]\nidle = 0\n", Path::new("")) else {
+            panic!("the address and the timeout should be rejected");
+        };
+        assert_eq!(2, problems.len());
+
+        let config = Config {
+            root: PathBuf::from("/does/not/exist"),
+            workers: 8,
+            max_workers: 4,
+            mounts: vec![Mount { prefix: String::from("/files/*path"), directory: env!("CARGO_MANIFEST_DIR").into(), listing: false }],
+            hosts: vec![VirtualHost {
+                names: vec![String::from("*.example.com"), String::from("www.*.com"), String::from("*.EXAMPLE.com")],
+                root: env!("CARGO_MANIFEST_DIR").into(),
+                listing: false
+            }],
+            ..Config::default()
+        };
+        let Err(ConfigError::Invalid(problems)) = config.validate() else {
+            panic!("the configuration should be invalid");
+        };
+        assert_eq!(5, problems.len(), "{problems:?}");
+        assert!(problems[0].contains("/does/not/exist"));
+    }
+}
@@ -0,0 +1,312 @@
+use crate::{response::Upgrade, shutdown::ConnectionGuard, Handler, Limits, Method, Params, ParseError, Request, Response, StatusCode, Version};
+use std::{
+    io::{self, prelude::*, BufReader},
+    net::{SocketAddr, TcpStream},
+    time::{Duration, Instant}
+};
+
+/// A connection requests can be answered on, which is either a plain TCP stream or one wrapped in TLS.
+pub(crate) trait Transport: Read + Write {
+    /// The TCP stream below, which the timeouts are set on.
+    fn socket(&self) -> &TcpStream;
+
+    /// Ends the connection cleanly, once the last response was written.
+    fn close(&mut self) {}
+}
+
+impl Transport for TcpStream {
+    fn socket(&self) -> &TcpStream {
+        self
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Transport for rustls::StreamOwned<rustls::ServerConnection, TcpStream> {
+    fn socket(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    // Without a `close_notify`, the client can't tell the end of the connection from an attacker cutting it short.
+    fn close(&mut self) {
+        self.conn.send_close_notify();
+        let _ = self.flush();
+    }
+}
+
+/// A connection that was taken over after a `101 Switching Protocols` response.
+pub(crate) struct Upgraded {
+    pub(crate) stream: Box<dyn Transport + Send>,
+    /// What the client sent after the request, which has to be read before the stream.
+    pub(crate) buffered: Vec<u8>,
+    /// Keeps the connection known to a `Shutdown` as long as it's open.
+    pub(crate) guard: Option<ConnectionGuard>
+}
+
+/// Settings for how long a connection is kept open and how much data may go through it.
+#[derive(Clone, Debug)]
+pub struct ConnectionOptions {
+    /// How long to wait for the next request before closing an idle connection.
+    pub idle_timeout: Duration,
+    /// How long a client may take to send the request line and header fields, once the first byte arrived.
+    /// With TLS, it also limits how long the handshake may take.
+    pub header_timeout: Duration,
+    /// How long a client may take to send the body, once the header fields are in.
+    pub body_timeout: Duration,
+    /// How long a single write to the client may block, e.g. because the client doesn't read.
+    pub write_timeout: Duration,
+    /// How many requests are answered on one connection before it is closed.
+    pub max_requests: usize,
+    pub limits: Limits,
+    /// The largest streamed response body a handler may write.
+    pub max_streamed_body: u64
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        ConnectionOptions {
+            idle_timeout: Duration::from_secs(5),
+            header_timeout: Duration::from_secs(10),
+            body_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(10),
+            max_requests: 100,
+            limits: Limits::default(),
+            max_streamed_body: 64 * 1024 * 1024
+        }
+    }
+}
+
+/// Answers requests on `stream` until the client or the options ask for the connection to be closed.
+///
+/// Requests are read through one buffered reader, so requests that were pipelined (sent before the previous response arrived) are simply answered in order.
+pub fn serve_connection<H>(stream: TcpStream, handler: &H, options: &ConnectionOptions)
+    where H: Handler + ?Sized {
+    serve(stream, handler, options, None);
+}
+
+/// Like `serve_connection`, but reports to `guard` whether the connection is idle, and stops keeping it alive once a shutdown started.
+pub(crate) fn serve<S, H>(stream: S, handler: &H, options: &ConnectionOptions, guard: Option<ConnectionGuard>)
+    where S: Transport + Send + 'static,
+          H: Handler + ?Sized {
+    let peer_addr = stream.socket().peer_addr().ok();
+    if stream.socket().set_write_timeout(Some(options.write_timeout)).is_err() {
+        return;
+    }
+    // Responses are written through the reader, which only buffers what is read, so both can share the same stream.
+    let mut reader = BufReader::new(TimedStream { stream, deadline: None });
+    match answer(&mut reader, handler, options, guard.as_ref(), peer_addr) {
+        Some(Upgrade(upgrade)) => {
+            // Whatever the client sent right after the request was already read into the buffer, so it's handed over too.
+            let buffered = reader.buffer().to_vec();
+            upgrade(Upgraded {
+                stream: Box::new(reader.into_inner().stream),
+                buffered,
+                guard
+            });
+        }
+        None => reader.get_mut().stream.close()
+    }
+}
+
+fn answer<S, H>(reader: &mut BufReader<TimedStream<S>>, handler: &H, options: &ConnectionOptions, guard: Option<&ConnectionGuard>, peer_addr: Option<SocketAddr>) -> Option<Upgrade>
+    where S: Transport,
+          H: Handler + ?Sized {
+    let mut served = 0;
+    loop {
+        if guard.is_some_and(|guard| !guard.set_idle(true)) {
+            return None;
+        }
+        // Waiting for the next request isn't the client's fault, so an idle connection is closed without a response.
+        reader.get_mut().deadline = Some(Instant::now() + options.idle_timeout);
+        match reader.fill_buf() {
+            Ok(buffered) if !buffered.is_empty() => {}
+            _ => return None
+        }
+        if let Some(guard) = guard {
+            guard.set_idle(false);
+        }
+
+        // The timeouts cover the whole head and the whole body, so a client can't keep a worker busy by sending one byte at a time.
+        reader.get_mut().deadline = Some(Instant::now() + options.header_timeout);
+        let parsed = Request::parse_head(reader, &options.limits).and_then(|mut request| {
+            reader.get_mut().deadline = Some(Instant::now() + options.body_timeout);
+            request.read_body(reader, &options.limits)?;
+            Ok(request)
+        });
+        let mut request = match parsed {
+            Ok(request) => request,
+            // There's nobody left to answer, if the client is already gone.
+            Err(ParseError::ConnectionClosed | ParseError::Io(_)) => return None,
+            Err(error) => {
+                let response = Response::text(error.status(), format!("{error}\n")).with_header("Connection", "close");
+                let _ = response.write_to(reader.get_mut(), false);
+                return None;
+            }
+        };
+        served += 1;
+        request.set_peer_addr(peer_addr);
+
+        let mut response = handler.handle(&request, &Params::default());
+        // The connection belongs to someone else after a `101 Switching Protocols`, so it isn't kept alive or closed here.
+        if let Some(upgrade) = response.take_upgrade().filter(|_| response.status() == StatusCode::SwitchingProtocols) {
+            return response.write_to(reader.get_mut(), false).ok().map(|()| upgrade);
+        }
+        // HTTP/1.0 clients don't know chunked bodies, so they get the whole stream at once.
+        if request.version() == Version::Http10 && response.body().len().is_none() {
+            response = match response.buffer_stream(options.max_streamed_body) {
+                Ok(response) => response,
+                Err(_) => Response::text(StatusCode::InternalServerError, "Internal Server Error\n")
+            };
+        }
+        let keep_alive = wants_keep_alive(&request)
+            && !response.headers().has_token("Connection", "close")
+            && served < options.max_requests
+            && !guard.is_some_and(ConnectionGuard::is_shutting_down);
+        if keep_alive {
+            // HTTP/1.1 connections are persistent by default, but HTTP/1.0 clients have to be told.
+            if request.version() == Version::Http10 {
+                response.headers_mut().insert("Connection", "keep-alive");
+            }
+            let timeout = options.idle_timeout.as_secs();
+            let remaining = options.max_requests - served;
+            response.headers_mut().insert("Keep-Alive", format!("timeout={timeout}, max={remaining}"));
+        } else {
+            response.headers_mut().insert("Connection", "close");
+        }
+
+        let head_only = request.method() == Method::Head;
+        if response.write_with_limit(reader.get_mut(), head_only, options.max_streamed_body).is_err() || !keep_alive {
+            return None;
+        }
+    }
+}
+
+// Reads from a stream, but gives up once the deadline has passed, no matter how slowly the bytes trickle in.
+// A plain read timeout would start over with every byte.
+struct TimedStream<S> {
+    stream: S,
+    deadline: Option<Instant>
+}
+
+impl<S: Transport> Read for TimedStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            // A timeout of zero would mean waiting forever.
+            if remaining.is_zero() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            self.stream.socket().set_read_timeout(Some(remaining))?;
+        }
+        self.stream.read(buf)
+    }
+}
+
+impl<S: Transport> Write for TimedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+fn wants_keep_alive(request: &Request) -> bool {
+    let headers = request.headers();
+    match request.version() {
+        Version::Http11 => !headers.has_token("Connection", "close"),
+        Version::Http10 => headers.has_token("Connection", "keep-alive")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Router;
+    use std::{
+        io::BufRead,
+        net::TcpListener,
+        thread
+    };
+
+    fn start(options: ConnectionOptions) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut router = Router::new();
+            router.get("/:name", |_: &Request, params: &Params| {
+                Response::text(StatusCode::Ok, params.get("name").unwrap_or_default())
+            });
+            let (stream, _) = listener.accept().unwrap();
+            serve_connection(stream, &router, &options);
+        });
+        TcpStream::connect(address).unwrap()
+    }
+
+    fn read_response(reader: &mut impl BufRead) -> (String, String) {
+        let mut head = String::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if let Some(value) = line.strip_prefix("Content-Length: ") {
+                length = value.trim().parse().unwrap();
+            }
+            if line == "\r\n" {
+                break;
+            }
+            head.push_str(&line);
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        (head, String::from_utf8(body).unwrap())
+    }
+
+    #[test]
+    fn answers_pipelined_requests_in_order() {
+        let mut stream = start(ConnectionOptions::default());
+        stream.write_all(b"GET /one HTTP/1.1\r\n\r\nGET /two HTTP/1.1\r\n\r\nGET /three HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+
+        let mut reader = BufReader::new(stream);
+        assert_eq!("one", read_response(&mut reader).1);
+        assert_eq!("two", read_response(&mut reader).1);
+        let (head, body) = read_response(&mut reader);
+        assert_eq!("three", body);
+        assert!(head.contains("Connection: close"));
+        assert_eq!(0, reader.read(&mut [0; 1]).unwrap());
+    }
+
+    #[test]
+    fn closes_after_max_requests() {
+        let mut stream = start(ConnectionOptions {
+            max_requests: 2,
+            ..ConnectionOptions::default()
+        });
+        stream.write_all(b"GET /one HTTP/1.1\r\n\r\nGET /two HTTP/1.1\r\n\r\n").unwrap();
+
+        let mut reader = BufReader::new(stream);
+        let (head, _) = read_response(&mut reader);
+        assert!(head.contains("Keep-Alive: timeout=5, max=1"));
+        let (head, _) = read_response(&mut reader);
+        assert!(head.contains("Connection: close"));
+        assert_eq!(0, reader.read(&mut [0; 1]).unwrap());
+    }
+
+    #[test]
+    fn times_out_clients_that_send_too_slowly() {
+        let mut stream = start(ConnectionOptions {
+            header_timeout: Duration::from_millis(200),
+            ..ConnectionOptions::default()
+        });
+        // Every byte arrives well within any per-read timeout, but the head as a whole takes too long.
+        // The client stops just before the deadline, so that the server doesn't get bytes it would never read.
+        for byte in b"GET /slo" {
+            stream.write_all(&[*byte]).unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        let mut reader = BufReader::new(stream);
+        let (head, _) = read_response(&mut reader);
+        assert!(head.starts_with("HTTP/1.1 408 Request Timeout"));
+    }
+}
@@ -0,0 +1,136 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Formats `time` like `Sun, 06 Nov 1994 08:49:37 GMT`, which is the only date format HTTP servers are allowed to send.
+pub fn format_http_date(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    let days = seconds / 86_400;
+    let seconds_of_day = seconds % 86_400;
+    let (year, month, day) = civil_from_days(days as i64);
+    // 1970-01-01 was a Thursday, which is why `DAYS` starts there.
+    let weekday = DAYS[(days % 7) as usize];
+    format!(
+        "{weekday}, {day:02} {} {year} {:02}:{:02}:{:02} GMT",
+        MONTHS[month as usize - 1],
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    )
+}
+
+/// Formats `time` like `06/Nov/1994:08:49:37 +0000`, which is how the Common Log Format writes dates.
+pub(crate) fn format_log_date(time: SystemTime) -> String {
+    let (year, month, day, seconds_of_day) = civil_time(time);
+    format!(
+        "{day:02}/{}/{year}:{:02}:{:02}:{:02} +0000",
+        MONTHS[month as usize - 1],
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    )
+}
+
+/// Formats `time` like `1994-11-06T08:49:37Z`, as RFC 3339 (and most JSON consumers) expect it.
+pub(crate) fn format_rfc3339(time: SystemTime) -> String {
+    let (year, month, day, seconds_of_day) = civil_time(time);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds_of_day / 3600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60
+    )
+}
+
+fn civil_time(time: SystemTime) -> (i64, u32, u32, u64) {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    (year, month, day, seconds % 86_400)
+}
+
+/// Parses the three date formats HTTP clients are allowed to send.
+///
+/// Besides the preferred `Sun, 06 Nov 1994 08:49:37 GMT`, recipients still have to understand the obsolete `Sunday, 06-Nov-94 08:49:37 GMT` and `Sun Nov  6 08:49:37 1994`.
+pub fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = date.split_whitespace().collect();
+    let (day, month, year, time) = match parts.as_slice() {
+        [_, day, month, year, time, "GMT"] => (*day, *month, year.parse().ok()?, *time),
+        [_, date, time, "GMT"] => {
+            let mut date = date.split('-');
+            let (day, month, year) = (date.next()?, date.next()?, date.next()?);
+            let year: i64 = year.parse().ok()?;
+            // The obsolete format only has two digits for the year, so anything before 70 is taken to be after 2000.
+            let year = if year < 70 { 2000 + year } else if year < 100 { 1900 + year } else { year };
+            (day, month, year, *time)
+        }
+        [_, month, day, time, year] => (*day, *month, year.parse().ok()?, *time),
+        _ => return None
+    };
+    let day: u32 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|name| *name == month)? as u32 + 1;
+    let mut time = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+    if !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 60 || time.next().is_some() {
+        return None;
+    }
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(days * 86_400 + hours * 3600 + minutes * 60 + seconds))
+}
+
+// This is Howard Hinnant's `civil_from_days` algorithm, which turns a day count into a proleptic Gregorian date.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// And this is its inverse, `days_from_civil`.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = i64::from(month);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_imf_fixdate() {
+        let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!("Sun, 06 Nov 1994 08:49:37 GMT", format_http_date(time));
+        assert_eq!("Thu, 01 Jan 1970 00:00:00 GMT", format_http_date(UNIX_EPOCH));
+        assert_eq!("06/Nov/1994:08:49:37 +0000", format_log_date(time));
+        assert_eq!("1994-11-06T08:49:37Z", format_rfc3339(time));
+    }
+
+    #[test]
+    fn parses_all_formats() {
+        let expected = Some(UNIX_EPOCH + Duration::from_secs(784_111_777));
+        assert_eq!(expected, parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"));
+        assert_eq!(expected, parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"));
+        assert_eq!(expected, parse_http_date("Sun Nov  6 08:49:37 1994"));
+        assert_eq!(None, parse_http_date("yesterday"));
+
+        let leap_day = UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(Some(leap_day), parse_http_date(&format_http_date(leap_day)));
+    }
+}
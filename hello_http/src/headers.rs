@@ -0,0 +1,110 @@
+use std::fmt;
+
+/// An ordered list of header fields whose names are compared case-insensitively.
+///
+/// Header names are case-insensitive in HTTP, so `content-length` and `Content-Length` refer to the same field.
+/// The original spelling is kept, because some clients are picky about how the response headers look.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Headers {
+    entries: Vec<(String, String)>
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Headers { entries: Vec::new() }
+    }
+
+    /// Returns the value of the first field called `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the values of all fields called `name` in the order they were added.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Checks whether any field called `name` lists `token` in its comma-separated value, e.g. `Connection: keep-alive, Upgrade`.
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Replaces every field called `name` with a single field.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.entries.push((name, value.into()));
+    }
+
+    /// Adds another field, even if one with the same name already exists.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl fmt::Display for Headers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in self.iter() {
+            write!(f, "{name}: {value}\r\n")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_case_insensitive() {
+        let mut headers = Headers::new();
+        headers.append("Content-Type", "text/html");
+        headers.append("accept", "text/html");
+        headers.append("ACCEPT", "*/*");
+
+        assert_eq!(Some("text/html"), headers.get("content-type"));
+        assert_eq!(vec!["text/html", "*/*"], headers.get_all("Accept").collect::<Vec<_>>());
+
+        headers.insert("Accept", "image/png");
+        assert_eq!(vec!["image/png"], headers.get_all("accept").collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn finds_tokens_in_lists() {
+        let mut headers = Headers::new();
+        headers.append("Connection", "keep-alive, Upgrade");
+
+        assert!(headers.has_token("connection", "upgrade"));
+        assert!(!headers.has_token("connection", "close"));
+    }
+}
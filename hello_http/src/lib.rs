@@ -1,99 +1,50 @@
-use std::{
-    sync::{
-        Arc,
-        mpsc,
-        mpsc::Receiver,
-        mpsc::Sender,
-        Mutex},
-    thread,
-    thread::{JoinHandle}};
-
-struct Worker {
-    id: usize,
-    thread: Option<JoinHandle<()>>
-}
-
-impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<Receiver<Job>>>) -> Self {
-        // If the operating system can't create any more threads, `spawn` will panic.
-        // To avoid panicking, you can use `Builder::spawn` instead.
-        let thread = thread::spawn(move || loop {
-            // A "chained" call like this immediately drops any temporary values in between, as soon as the `let` finishes.
-            // The Rust book explains that using `while let Ok(job) = receiver.[...].recv()` wouldn't work, because `while let` does not drop the values until the end of the associated block.
-            let message = receiver
-                // A mutex might be in a so-called "poisoned" state, if another thread that holds it panicked and didn't release it.
-                .lock()
-                .unwrap()
-                .recv();
-            match message {
-                Ok(job) => job(),
-                Err(_) => {
-                    println!("Worker {id} disconnected; shutting down.");
-                    break;
-                }
-            };
-        });
-        Worker { id, thread: Some(thread) }
-    }
-}
-
-pub struct PoolCreationError(usize);
-type Job = Box<dyn FnOnce() + Send + 'static>;
-
-pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<Sender<Job>>
-}
-
-impl ThreadPool {
-    // To generate documentation, use `cargo doc`.
-    /// Creates a new `ThreadPool` with `size` as the number of available threads.
-    ///
-    /// # Panics
-    ///
-    /// This function will panic, when `size = 0`.
-    pub fn new(size: usize) -> Self {
-        assert!(size > 0);
-
-        let (sender, receiver) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(receiver));
-        let mut workers = Vec::with_capacity(size);
-        for worker_id in 0..size {
-            workers.push(Worker::new(worker_id, Arc::clone(&receiver)))
-        }
-
-        ThreadPool { workers, sender: Some(sender) }
-    }
-
-    pub fn build(size: usize) -> Result<ThreadPool, PoolCreationError> {
-        if size > 0 {
-            Ok(Self::new(size))
-        } else {
-            Err(PoolCreationError(size))
-        }
-    }
-
-    pub fn execute<F>(&self, f: F)
-        where F: FnOnce() + Send + 'static {
-        let job = Box::new(f);
-        self.sender
-            .as_ref()
-            .unwrap()
-            .send(job)
-            .unwrap();
-    }
-}
-
-impl Drop for ThreadPool {
-    fn drop(&mut self) {
-        drop(self.sender.take());
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
-            if let Some(thread) = worker.thread.take() {
-                thread
-                    .join()
-                    .unwrap();
-            }
-        }
-    }
-}
\ No newline at end of file
+mod access_log;
+mod auth;
+mod chunked;
+mod compression;
+mod config;
+mod connection;
+mod date;
+mod headers;
+mod log;
+mod middleware;
+mod mime;
+mod pool;
+mod prometheus;
+mod range;
+mod rate_limit;
+mod request;
+mod response;
+mod router;
+mod server;
+mod shutdown;
+mod static_files;
+mod status;
+#[cfg(feature = "tls")]
+mod tls;
+mod virtual_host;
+mod websocket;
+
+pub use access_log::{AccessLog, LogFormat};
+pub use auth::{BasicAuth, BearerAuth, Htpasswd, HtpasswdError};
+pub use chunked::ChunkedWriter;
+pub use compression::Compression;
+pub use config::{Command, Config, ConfigError, Mount, TlsFiles, VirtualHost, USAGE};
+pub use connection::{serve_connection, ConnectionOptions};
+pub use headers::Headers;
+pub use log::{print_log, LogLevel, Logger};
+pub use middleware::{Chain, Context, Middleware};
+pub use pool::{Clock, Histogram, JobError, JobHandle, JobPanic, ManualClock, OverflowPolicy, PanicHook, PoolBuilder, PoolCreationError, PoolMetrics, PoolMonitor, QueueFullError, Scheduler, Scope, SystemClock, ThreadPool, TimerHandle};
+pub use prometheus::MetricsHandler;
+pub use rate_limit::{RateLimit, RateLimitKey};
+pub use request::{Limits, Method, ParseError, Request, Version};
+pub use response::{Body, Response, StreamFn};
+pub use router::{Handler, Params, Router};
+pub use server::Server;
+pub use shutdown::{Shutdown, ShutdownHandler};
+pub use static_files::StaticFiles;
+pub use status::StatusCode;
+#[cfg(feature = "tls")]
+pub use tls::{TlsConfig, TlsError};
+pub use virtual_host::{is_host_pattern, VirtualHosts};
+pub use websocket::{CloseCode, Message, WebSocket, WebSocketHandler, WebSocketRoute};
@@ -0,0 +1,33 @@
+use std::fmt;
+
+/// How important a log message is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR"
+        })
+    }
+}
+
+/// Receives the messages of a `ThreadPool` or a `Server`, e.g. to pass them on to a logging library or to silence them.
+pub type Logger = dyn Fn(LogLevel, &str) + Send + Sync;
+
+/// The default logger: warnings and errors go to stderr, everything else to stdout.
+pub fn print_log(level: LogLevel, message: &str) {
+    if level >= LogLevel::Warn {
+        eprintln!("{message}");
+    } else {
+        println!("{message}");
+    }
+}
@@ -1,54 +1,162 @@
-use hello_http::ThreadPool;
+use hello_http::{AccessLog, BearerAuth, Body, Chain, Command, Compression, Config, ConfigError, MetricsHandler, OverflowPolicy, Params, PoolMonitor, RateLimit, Request, Response, Router, Server, Shutdown, ShutdownHandler, StaticFiles, StatusCode, ThreadPool, VirtualHosts, WebSocket, WebSocketRoute, USAGE};
 // I think this is the first time using a complex structure to import like this.
 use std::{
-    fs,
-    io::{prelude::*, BufReader},
-    net::{TcpListener, TcpStream},
+    env,
+    fmt,
+    fs::File,
+    io,
+    net::{SocketAddr, TcpListener},
+    path::Path,
+    process,
     thread,
     time::Duration
 };
 
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-    let pool = ThreadPool::new(4);
-    // A real web server would not finish after processing two requests.
-    // Instead, it shows how we could handle a graceful shutdown.
-    for stream in listener.incoming().take(2) {
-        // A stream (in this case `TcpStream` represents an open connection between server and client.
-        let stream = stream.unwrap();
-        pool.execute(|| handle_connection(stream));
+    // Everything can come from a TOML file given with `--config`, and flags override it, see `hello_http --help`.
+    // The document root can still be passed as the only argument, e.g. `cargo run -p hello_http -- ./site`.
+    let config = match Command::from_args(env::args().skip(1)) {
+        Ok(Command::Serve(config)) => config,
+        Ok(Command::CheckConfig(config)) => {
+            if let Err(error) = config.validate() {
+                eprintln!("{error}");
+                process::exit(1);
+            }
+            println!("The configuration is valid.");
+            return;
+        }
+        Ok(Command::Help) => {
+            print!("{USAGE}");
+            return;
+        }
+        Err(error @ ConfigError::Usage(_)) => {
+            eprintln!("{error}\n\n{USAGE}");
+            process::exit(2);
+        }
+        Err(error) => fail(error)
+    };
+    if let Err(error) = config.validate() {
+        fail(error);
+    }
+
+    // Bursts get up to `max_workers` workers, which retire again after a minute without work.
+    // Connections beyond what the queue can hold are answered with `503 Service Unavailable` instead of piling up.
+    let pool = match ThreadPool::builder().min_workers(config.workers).max_workers(config.max_workers).thread_name("http").build() {
+        Ok(pool) => pool
+            .with_queue_capacity(config.queue_capacity)
+            .with_overflow_policy(OverflowPolicy::Reject),
+        Err(error) => fail(format!("Can't start the workers: {error}"))
+    };
+    let shutdown = Shutdown::new();
+    // `GET /metrics` reads the pool's numbers through a monitor, because the pool itself goes to the server.
+    let router = match routes(&config, &shutdown, pool.monitor()) {
+        Ok(router) => router,
+        Err(error) => fail(format!("Can't serve {}: {error}", config.root.display()))
+    };
+    if let Err(error) = shutdown.register_signals() {
+        fail(format!("Can't listen for signals: {error}"));
+    }
+
+    // Every request is logged in the configured format, to stderr unless there's a log file.
+    let access_log = match &config.log_file {
+        Some(path) => AccessLog::file(config.log_format, path)
+            .unwrap_or_else(|error| fail(format!("Can't open the log file {}: {error}", path.display()))),
+        None => AccessLog::stderr(config.log_format)
+    };
+    let app = match sites(&config, router) {
+        Ok(app) => app,
+        Err((root, error)) => fail(format!("Can't serve {}: {error}", root.display()))
+    };
+    let app = app
+        .with_middleware(access_log)
+        .with_middleware(Compression::new().with_max_size(config.connection.max_streamed_body));
+    // The server stops on Ctrl+C, `SIGTERM` or `POST /admin/shutdown`.
+    // Requests that are still running get the grace period to finish, then the `ThreadPool` is dropped, which joins the workers.
+    let mut server = Server::new(app, pool)
+        .with_options(config.connection.clone())
+        .with_shutdown(shutdown)
+        .with_grace_period(config.grace_period);
+    for address in &config.listen {
+        server = server.with_listener(bind(*address));
+    }
+    #[cfg(feature = "tls")]
+    if let Some(files) = config.tls.as_ref().filter(|_| !config.https.is_empty()) {
+        let tls = hello_http::TlsConfig::from_pem_files(&files.certificate, &files.key)
+            .unwrap_or_else(|error| fail(format!("Can't use {} and {}: {error}", files.certificate.display(), files.key.display())));
+        for address in &config.https {
+            server = server.with_https(bind(*address), tls.clone());
+        }
+    }
+    if let Err(error) = server.serve() {
+        fail(format!("Server failed: {error}"));
     }
 
     println!("Shutting down.");
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    let buf_reader = BufReader::new(&mut stream);
-    let request_line = buf_reader
-        .lines()
-        .next()
-        .unwrap()
-        .unwrap();
-    // Interestingly, the discard `_` can also be used to discard the generic type.
-    //     let http_request: Vec<_> = buf_reader
-    //         .lines()
-    //         .map(|result| result.unwrap())
-    //         .take_while(|line| !line.is_empty())
-    //         .collect();
-    // I'm not sure if `#` in the `println` macro has been used before, but it essentially pretty-prints the value.
-    //     println!("Request: {http_request:?}");
-
-    let (status_line, filename) = match &request_line[..] {
-        "GET / HTTP/1.1" => ("HTTP/1.1 200 OK", "hello_http/hello.html"),
-        "GET /sleep HTTP/1.1" => {
+fn bind(address: SocketAddr) -> TcpListener {
+    TcpListener::bind(address).unwrap_or_else(|error| fail(format!("Can't listen on {address}: {error}")))
+}
+
+fn fail(error: impl fmt::Display) -> ! {
+    eprintln!("{error}");
+    process::exit(1);
+}
+
+fn routes(config: &Config, shutdown: &Shutdown, monitor: PoolMonitor) -> io::Result<Router> {
+    let files = StaticFiles::new(&config.root)?.with_not_found_page("404.html");
+    let index = files.root().join("index.html");
+    let mut router = Router::new();
+    router
+        .post("/admin/shutdown", ShutdownHandler::new(shutdown.clone()))
+        .get("/metrics", MetricsHandler::new(monitor))
+        .get("/sleep", move |_: &Request, _: &Params| {
             thread::sleep(Duration::from_secs(5));
-            ("HTTP/1.1 200 OK", "hello_http/hello.html")
-        // Apparently, if you use a scope, there's no need for a comma, even if another item follows.
-        }
-        _ => ("HTTP/1.1 404 NOT FOUND", "hello_http/404.html")
-    };
-    let contents = fs::read_to_string(filename).unwrap();
-    let length = contents.len();
-    let response = format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
-    stream.write_all(response.as_bytes()).unwrap();
+            html_file(&index)
+        })
+        // A WebSocket that sends every message back, e.g. to try the connection from the browser console.
+        .get("/echo", WebSocketRoute::new(|socket: &WebSocket, message| {
+            let _ = socket.send(message);
+        }));
+    // Routes are tried in order, so the mounts have to come before the root catches every path.
+    for mount in &config.mounts {
+        let files = StaticFiles::new(&mount.directory)?.with_directory_listing(mount.listing);
+        router.get(&format!("{}/*path", mount.prefix), files);
+    }
+    router.get("/*path", files);
+    // `/sleep` holds a worker for 5 seconds, so every client only gets to call it a few times a minute.
+    router.wrap("/sleep", RateLimit::new(3, Duration::from_secs(60)));
+    // With a token in the environment, the admin endpoints also want it as `Authorization: Bearer`.
+    if let Ok(token) = env::var("HELLO_HTTP_ADMIN_TOKEN") {
+        router.wrap("/admin", BearerAuth::new("admin").with_token(token, "admin"));
+    }
+    Ok(router)
+}
+
+// Without virtual hosts, every request goes to the router, no matter which `Host` it asks for.
+// With them, each site only gets its own files, and the router with the admin and metrics endpoints becomes the default.
+fn sites(config: &Config, router: Router) -> Result<Chain, (&Path, io::Error)> {
+    if config.hosts.is_empty() {
+        return Ok(Chain::new(router));
+    }
+    let mut hosts = VirtualHosts::new();
+    for host in &config.hosts {
+        let files = StaticFiles::new(&host.root)
+            .map_err(|error| (host.root.as_path(), error))?
+            .with_directory_listing(host.listing)
+            .with_not_found_page("404.html");
+        let names: Vec<&str> = host.names.iter().map(String::as_str).collect();
+        hosts.host(&names, files);
+    }
+    hosts.default_host(router);
+    Ok(Chain::new(hosts))
+}
+
+fn html_file(path: &Path) -> Response {
+    match File::open(path).and_then(Body::file) {
+        Ok(body) => Response::new(StatusCode::Ok)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(body),
+        Err(_) => Response::text(StatusCode::InternalServerError, "Internal Server Error\n")
+    }
 }
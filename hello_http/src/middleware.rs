@@ -0,0 +1,226 @@
+use crate::{Handler, Params, Request, Response};
+use std::{
+    cell::Cell,
+    time::{Duration, Instant}
+};
+
+/// What the middleware of one request want to tell each other.
+#[derive(Debug)]
+pub struct Context {
+    started: Instant,
+    user: Option<String>
+}
+
+impl Context {
+    fn new() -> Self {
+        Context {
+            started: Instant::now(),
+            user: None
+        }
+    }
+
+    /// How long ago the chain started working on the request.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// The user the request was authenticated as, if any middleware did that.
+    pub fn user(&self) -> Option<&str> {
+        self.user.as_deref()
+    }
+
+    pub fn set_user(&mut self, user: impl Into<String>) {
+        self.user = Some(user.into());
+    }
+}
+
+/// Code that runs around a handler, e.g. to log requests or to turn away clients that aren't allowed in.
+pub trait Middleware: Send + Sync {
+    /// Runs before the handler.
+    /// Returning a response answers the request right away, without calling the handler or the middleware further in.
+    fn before(&self, _request: &Request, _context: &mut Context) -> Option<Response> {
+        None
+    }
+
+    /// Runs after the handler, or after a middleware further in answered early, and may change the response.
+    fn after(&self, _request: &Request, response: Response, _context: &mut Context) -> Response {
+        response
+    }
+}
+
+/// Wraps a handler in middleware.
+///
+/// The middleware added first is the outermost one:
+/// its `before` runs first and its `after` runs last, so it sees every response, even one another middleware answered early with.
+pub struct Chain {
+    middleware: Vec<Box<dyn Middleware>>,
+    handler: Box<dyn Handler>
+}
+
+impl Chain {
+    pub fn new<H>(handler: H) -> Self
+        where H: Handler + 'static {
+        Chain {
+            middleware: Vec::new(),
+            handler: Box::new(handler)
+        }
+    }
+
+    /// Adds `middleware` inside the ones that were added before.
+    pub fn with_middleware<M>(mut self, middleware: M) -> Self
+        where M: Middleware + 'static {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+}
+
+impl Handler for Chain {
+    fn handle(&self, request: &Request, params: &Params) -> Response {
+        let middleware: Vec<&dyn Middleware> = self.middleware
+            .iter()
+            .map(|middleware| &**middleware)
+            .collect();
+        run(&middleware, request, || self.handler.handle(request, params))
+    }
+}
+
+thread_local! {
+    // While a handler runs, the context of its chain waits here,
+    // so that a chain nested inside the handler, e.g. middleware on a router prefix, works with the same context.
+    static LENT: Cell<Option<Context>> = const { Cell::new(None) };
+}
+
+// Makes sure a lent context doesn't stay behind for the next request on this thread, if the handler panics.
+struct Reclaim;
+
+impl Drop for Reclaim {
+    fn drop(&mut self) {
+        LENT.take();
+    }
+}
+
+/// Runs `middleware` around `handle`, the first one being the outermost.
+pub(crate) fn run<F>(middleware: &[&dyn Middleware], request: &Request, handle: F) -> Response
+    where F: FnOnce() -> Response {
+    let outer = LENT.take();
+    let nested = outer.is_some();
+    let mut context = outer.unwrap_or_else(Context::new);
+    let mut entered = 0;
+    let mut early = None;
+    for middleware in middleware {
+        entered += 1;
+        early = middleware.before(request, &mut context);
+        if early.is_some() {
+            break;
+        }
+    }
+    let mut response = match early {
+        Some(response) => response,
+        None => {
+            LENT.set(Some(context));
+            let reclaim = Reclaim;
+            let response = handle();
+            context = LENT.take().unwrap_or_else(Context::new);
+            drop(reclaim);
+            response
+        }
+    };
+    // Only the middleware whose `before` ran get to see the response, from the inside out.
+    for middleware in middleware[..entered].iter().rev() {
+        response = middleware.after(request, response, &mut context);
+    }
+    if nested {
+        LENT.set(Some(context));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StatusCode;
+    use std::sync::{Arc, Mutex};
+
+    struct Trace {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<String>>>,
+        answer: bool
+    }
+
+    impl Middleware for Trace {
+        fn before(&self, _request: &Request, _context: &mut Context) -> Option<Response> {
+            self.calls.lock().unwrap().push(format!("before {}", self.name));
+            self.answer.then(|| Response::text(StatusCode::Forbidden, "no"))
+        }
+
+        fn after(&self, _request: &Request, mut response: Response, _context: &mut Context) -> Response {
+            self.calls.lock().unwrap().push(format!("after {}", self.name));
+            response.headers_mut().append("X-Seen-By", self.name);
+            response
+        }
+    }
+
+    fn chain(answer_in_inner: bool, calls: &Arc<Mutex<Vec<String>>>) -> Chain {
+        let handler_calls = Arc::clone(calls);
+        Chain::new(move |_: &Request, _: &Params| {
+            handler_calls.lock().unwrap().push(String::from("handler"));
+            Response::text(StatusCode::Ok, "yes")
+        })
+            .with_middleware(Trace { name: "outer", calls: Arc::clone(calls), answer: false })
+            .with_middleware(Trace { name: "inner", calls: Arc::clone(calls), answer: answer_in_inner })
+    }
+
+    fn request() -> Request {
+        Request::parse(&mut "GET / HTTP/1.1\r\n\r\n".as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn runs_hooks_around_the_handler() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let response = chain(false, &calls).handle(&request(), &Params::default());
+
+        assert_eq!(StatusCode::Ok, response.status());
+        assert_eq!(vec!["before outer", "before inner", "handler", "after inner", "after outer"], *calls.lock().unwrap());
+        assert_eq!(vec!["inner", "outer"], response.headers().get_all("x-seen-by").collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn answers_early_without_the_handler() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let response = chain(true, &calls).handle(&request(), &Params::default());
+
+        assert_eq!(StatusCode::Forbidden, response.status());
+        assert_eq!(vec!["before outer", "before inner", "after inner", "after outer"], *calls.lock().unwrap());
+    }
+
+    struct Login;
+
+    impl Middleware for Login {
+        fn before(&self, _request: &Request, context: &mut Context) -> Option<Response> {
+            context.set_user("alice");
+            None
+        }
+    }
+
+    struct ShowUser;
+
+    impl Middleware for ShowUser {
+        fn after(&self, _request: &Request, response: Response, context: &mut Context) -> Response {
+            response.with_header("X-User", context.user().unwrap_or("-"))
+        }
+    }
+
+    #[test]
+    fn shares_the_context_with_nested_chains() {
+        let inner = Chain::new(|_: &Request, _: &Params| Response::text(StatusCode::Ok, "yes")).with_middleware(Login);
+        let outer = Chain::new(inner).with_middleware(ShowUser);
+
+        let response = outer.handle(&request(), &Params::default());
+        assert_eq!(Some("alice"), response.headers().get("x-user"));
+        // The next request on the same thread starts over.
+        let response = Chain::new(|_: &Request, _: &Params| Response::new(StatusCode::Ok))
+            .with_middleware(ShowUser)
+            .handle(&request(), &Params::default());
+        assert_eq!(Some("-"), response.headers().get("x-user"));
+    }
+}
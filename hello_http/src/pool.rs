@@ -0,0 +1,702 @@
+mod builder;
+mod handle;
+mod metrics;
+mod queue;
+mod scheduler;
+mod scope;
+mod stealing;
+mod timer;
+
+pub use builder::PoolBuilder;
+pub use handle::{JobError, JobHandle};
+pub use metrics::{Histogram, PoolMetrics};
+pub use queue::OverflowPolicy;
+pub use scheduler::Scheduler;
+pub use scope::Scope;
+pub use timer::{Clock, ManualClock, SystemClock, TimerHandle};
+
+use crate::log::{print_log, LogLevel, Logger};
+use metrics::Counters;
+use queue::Overflow;
+use scheduler::Backend;
+use timer::Timers;
+
+use std::{
+    any::Any,
+    error::Error,
+    fmt,
+    io,
+    num::NonZeroUsize,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+        mpsc,
+        Mutex,
+        MutexGuard,
+        PoisonError,
+        RwLock},
+    thread,
+    thread::{JoinHandle},
+    time::Instant};
+
+struct Worker {
+    id: usize,
+    thread: Option<JoinHandle<()>>
+}
+
+impl Worker {
+    fn spawn(id: usize, shared: Arc<Shared>) -> io::Result<Self> {
+        // If the operating system can't create any more threads, `thread::spawn` will panic.
+        // `Builder::spawn` returns an error instead, and it can also name the thread and change its stack size.
+        let mut builder = thread::Builder::new().name(format!("{}-{id}", shared.thread_name));
+        if let Some(stack_size) = shared.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+        let thread = builder.spawn(move || run(id, shared))?;
+        Ok(Worker { id, thread: Some(thread) })
+    }
+}
+
+/// Starts a new worker for a place that was already reserved in the queue.
+fn add_worker(shared: &Arc<Shared>) -> io::Result<()> {
+    let id = shared.next_id.fetch_add(1, Ordering::Relaxed);
+    // The list stays locked until the worker is in it, so that the worker can't try to leave the list before it's there.
+    let mut workers = shared.workers();
+    workers.push(Worker::spawn(id, Arc::clone(shared))?);
+    Ok(())
+}
+
+fn run(id: usize, shared: Arc<Shared>) {
+    let _sentinel = Sentinel { id, shared: Arc::clone(&shared) };
+    // This is declared after the sentinel, so it's dropped first and a replacement finds everything the way this worker left it.
+    let local = shared.queue.local(id);
+    while let Some(job) = shared.queue.pop(&local) {
+        run_job(&shared, Some(id), job);
+    }
+    if shared.queue.is_closed() {
+        shared.log(LogLevel::Debug, &format!("Worker {id} disconnected; shutting down."));
+    } else {
+        // The worker has been idle for too long and retires.
+        // Nobody is going to join it, so its handle is dropped, which detaches the thread.
+        shared.workers().retain(|worker| worker.id != id);
+        shared.log(LogLevel::Debug, &format!("Worker {id} was idle for too long; shutting down."));
+    }
+}
+
+// Hands the task to the workers and hands it back, if it was rejected.
+fn dispatch(shared: &Arc<Shared>, task: Task) -> Result<(), Task> {
+    // Another worker is only started if none is waiting for this job, and the pool isn't at its maximum yet.
+    if shared.queue.reserve_worker() {
+        if let Err(error) = add_worker(shared) {
+            shared.queue.release_worker();
+            shared.log(LogLevel::Error, &format!("Failed to add a worker: {error}"));
+        }
+    }
+    match shared.queue.push(Job::new(task)) {
+        Ok(()) => Ok(()),
+        Err(Overflow::Rejected(job)) => Err(job.task),
+        Err(Overflow::RunHere(job)) => {
+            run_job(shared, None, job);
+            Ok(())
+        }
+    }
+}
+
+fn run_job(shared: &Shared, worker_id: Option<usize>, job: Job) {
+    let counters = &shared.counters;
+    let started = Instant::now();
+    counters.wait_time.record(started - job.queued_at);
+    // Jobs that run on the caller's thread don't occupy a worker.
+    if worker_id.is_some() {
+        counters.busy.fetch_add(1, Ordering::Relaxed);
+    }
+    // `AssertUnwindSafe` is fine here, because the job is gone after it panicked, so nobody can see it half-finished.
+    let result = panic::catch_unwind(AssertUnwindSafe(job.task));
+    counters.run_time.record(started.elapsed());
+    if worker_id.is_some() {
+        counters.busy.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    match result {
+        Ok(()) => {
+            counters.completed.fetch_add(1, Ordering::Relaxed);
+        }
+        Err(payload) => {
+            counters.panicked.fetch_add(1, Ordering::Relaxed);
+            let panic = JobPanic { worker_id, payload: &*payload };
+            let hook = shared.panic_hook
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone();
+            match hook {
+                Some(hook) => hook(&panic),
+                None => shared.log(LogLevel::Error, &describe_panic(&panic))
+            }
+        }
+    }
+}
+
+// Lives on a worker's stack and starts a replacement, if the worker unwinds for any reason, e.g. because the panic hook panicked.
+struct Sentinel {
+    id: usize,
+    shared: Arc<Shared>
+}
+
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            return;
+        }
+        let id = self.id;
+        let mut workers = self.shared.workers();
+        // Spawning with `Builder` matters even more here, because panicking while already unwinding aborts the whole process.
+        match Worker::spawn(id, Arc::clone(&self.shared)) {
+            Ok(replacement) => {
+                // The handle of the dying thread is replaced, which detaches it, but it's about to finish anyway.
+                workers.retain(|worker| worker.id != id);
+                workers.push(replacement);
+            }
+            Err(error) => {
+                self.shared.queue.release_worker();
+                self.shared.log(LogLevel::Error, &format!("Failed to replace worker {id}: {error}"));
+            }
+        }
+    }
+}
+
+struct Shared {
+    queue: Backend,
+    workers: Mutex<Vec<Worker>>,
+    next_id: AtomicUsize,
+    thread_name: String,
+    stack_size: Option<usize>,
+    // Without a hook, panics are logged.
+    panic_hook: RwLock<Option<Arc<PanicHook>>>,
+    logger: RwLock<Arc<Logger>>,
+    counters: Counters,
+    // The timers wake a clock, which only knows them weakly, so they have their own `Arc`.
+    timers: Arc<Timers>
+}
+
+impl Shared {
+    fn workers(&self) -> MutexGuard<'_, Vec<Worker>> {
+        self.workers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn log(&self, level: LogLevel, message: &str) {
+        let logger = self.logger
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        logger(level, message);
+    }
+
+    fn metrics(&self) -> PoolMetrics {
+        let counters = &self.counters;
+        let workers = self.queue.workers();
+        let busy = counters.busy.load(Ordering::Relaxed);
+        PoolMetrics {
+            queued: self.queue.queued(),
+            workers,
+            busy,
+            idle: workers.saturating_sub(busy),
+            completed: counters.completed.load(Ordering::Relaxed),
+            panicked: counters.panicked.load(Ordering::Relaxed),
+            wait_time: counters.wait_time.snapshot(),
+            run_time: counters.run_time.snapshot()
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PoolCreationError {
+    /// The maximum number of workers was 0.
+    NoWorkers,
+    /// The minimum number of workers was larger than the maximum.
+    InvalidBounds { min: usize, max: usize },
+    /// The operating system couldn't start a thread.
+    Spawn(io::Error)
+}
+
+impl fmt::Display for PoolCreationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolCreationError::NoWorkers => write!(f, "a thread pool can't have 0 threads"),
+            PoolCreationError::InvalidBounds { min, max } => write!(f, "a thread pool can't have at least {min}, but at most {max} threads"),
+            PoolCreationError::Spawn(error) => write!(f, "failed to start a worker: {error}")
+        }
+    }
+}
+
+impl Error for PoolCreationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PoolCreationError::Spawn(error) => Some(error),
+            _ => None
+        }
+    }
+}
+
+/// The job was turned away, because the pool's queue is full and its policy is `OverflowPolicy::Reject`.
+#[derive(Debug)]
+pub struct QueueFullError;
+
+impl fmt::Display for QueueFullError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the job queue is full")
+    }
+}
+
+impl Error for QueueFullError {}
+
+/// Describes a job that panicked on one of the pool's workers.
+pub struct JobPanic<'a> {
+    worker_id: Option<usize>,
+    payload: &'a (dyn Any + Send)
+}
+
+impl JobPanic<'_> {
+    /// The worker that ran the job, or `None` if it ran on the caller's thread because of `OverflowPolicy::CallerRuns`.
+    pub fn worker_id(&self) -> Option<usize> {
+        self.worker_id
+    }
+
+    /// The message that was passed to `panic!`, if there was one.
+    pub fn message(&self) -> Option<&str> {
+        panic_message(self.payload)
+    }
+
+    /// The value the job panicked with, for panics that were raised with `panic::panic_any`.
+    pub fn payload(&self) -> &(dyn Any + Send) {
+        self.payload
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    // `panic!` with a literal creates a `&str` payload, and with formatting arguments a `String`.
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+}
+
+/// Is called on the worker thread, whenever a job panics.
+pub type PanicHook = dyn Fn(&JobPanic<'_>) + Send + Sync;
+
+fn describe_panic(panic: &JobPanic<'_>) -> String {
+    let message = panic.message().unwrap_or("Box<dyn Any>");
+    match panic.worker_id() {
+        Some(id) => format!("Worker {id} caught a panicking job: {message}"),
+        None => format!("A job that ran on the caller's thread panicked: {message}")
+    }
+}
+
+type Task = Box<dyn FnOnce() + Send + 'static>;
+
+// A job remembers when it was queued, so that the time it waited for a worker can be measured.
+struct Job {
+    task: Task,
+    queued_at: Instant
+}
+
+impl Job {
+    fn new(task: Task) -> Self {
+        Job { task, queued_at: Instant::now() }
+    }
+}
+
+/// Reads the metrics of a `ThreadPool` from anywhere, e.g. from a handler that runs on the pool itself.
+///
+/// The monitor doesn't keep the workers alive; once the pool is dropped, the numbers just stop changing.
+#[derive(Clone)]
+pub struct PoolMonitor {
+    shared: Arc<Shared>
+}
+
+impl PoolMonitor {
+    pub fn metrics(&self) -> PoolMetrics {
+        self.shared.metrics()
+    }
+}
+
+/// Runs jobs on a set of worker threads.
+///
+/// `ThreadPool::new` creates a pool with a fixed number of workers.
+/// A pool from `ThreadPool::builder` can instead grow up to a maximum while jobs are piling up, and shrink back to its minimum when workers stay idle.
+///
+/// Jobs wait in a queue until a worker is free.
+/// By default, the queue can grow without limit, but it can be given a capacity and a policy for what happens to jobs that don't fit.
+/// A job that panics doesn't take its worker down with it; the panic is reported to the panic hook instead.
+/// Should a worker die anyway, it's replaced right away, so the pool doesn't shrink because of it.
+pub struct ThreadPool {
+    shared: Arc<Shared>
+}
+
+impl ThreadPool {
+    // To generate documentation, use `cargo doc`.
+    /// Creates a new `ThreadPool` with `size` as the number of available threads.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic, when `size = 0` or when the threads can't be started.
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0);
+
+        Self::build(size).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    pub fn build(size: usize) -> Result<ThreadPool, PoolCreationError> {
+        Self::builder()
+            .min_workers(size)
+            .max_workers(size)
+            .build()
+    }
+
+    /// Starts configuring a pool whose size can change.
+    pub fn builder() -> PoolBuilder {
+        PoolBuilder::new()
+    }
+
+    fn start(builder: PoolBuilder) -> Result<ThreadPool, PoolCreationError> {
+        let min = builder.min_workers;
+        let max = builder.max_workers.unwrap_or_else(|| {
+            let parallelism = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+            min.max(parallelism)
+        });
+        if max == 0 {
+            return Err(PoolCreationError::NoWorkers);
+        }
+        if min > max {
+            return Err(PoolCreationError::InvalidBounds { min, max });
+        }
+
+        let shared = Arc::new(Shared {
+            queue: Backend::new(builder.scheduler, min, max, builder.keep_alive),
+            workers: Mutex::new(Vec::with_capacity(max)),
+            next_id: AtomicUsize::new(0),
+            thread_name: builder.thread_name,
+            stack_size: builder.stack_size,
+            panic_hook: RwLock::new(None),
+            logger: RwLock::new(Arc::new(print_log)),
+            counters: Counters::new(),
+            timers: Arc::new(Timers::new())
+        });
+        // If a worker can't be started, dropping the pool stops the ones that already were.
+        let pool = ThreadPool { shared };
+        for _ in 0..pool.shared.queue.workers() {
+            add_worker(&pool.shared).map_err(PoolCreationError::Spawn)?;
+        }
+        Ok(pool)
+    }
+
+    /// Replaces what happens when a job panics. By default, the panic is logged as an error.
+    pub fn with_panic_hook<F>(self, hook: F) -> Self
+        where F: Fn(&JobPanic<'_>) + Send + Sync + 'static {
+        *self.shared.panic_hook.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sends the pool's messages, like workers starting and stopping, to `logger` instead of printing them.
+    pub fn with_logger<F>(self, logger: F) -> Self
+        where F: Fn(LogLevel, &str) + Send + Sync + 'static {
+        *self.shared.logger.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(logger);
+        self
+    }
+
+    /// Takes a snapshot of what the pool is doing.
+    pub fn metrics(&self) -> PoolMetrics {
+        self.shared.metrics()
+    }
+
+    /// Returns a handle to read the metrics after the pool was handed over, e.g. to a `Server`.
+    pub fn monitor(&self) -> PoolMonitor {
+        PoolMonitor { shared: Arc::clone(&self.shared) }
+    }
+
+    /// Limits how many jobs may wait for a free worker. What happens to jobs beyond that depends on the overflow policy.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic, when `capacity = 0`.
+    pub fn with_queue_capacity(self, capacity: usize) -> Self {
+        assert!(capacity > 0);
+        self.shared.queue.set_capacity(Some(capacity));
+        self
+    }
+
+    /// Decides what happens to jobs that don't fit into the queue. The default is `OverflowPolicy::Block`.
+    pub fn with_overflow_policy(self, policy: OverflowPolicy) -> Self {
+        self.shared.queue.set_policy(policy);
+        self
+    }
+
+    /// How many workers the pool has right now.
+    pub fn size(&self) -> usize {
+        self.shared.queue.workers()
+    }
+
+    /// Runs `f` on one of the workers.
+    ///
+    /// If the queue is full and the policy is `OverflowPolicy::Reject`, the job is dropped; use `try_execute` to find out when that happens.
+    pub fn execute<F>(&self, f: F)
+        where F: FnOnce() + Send + 'static {
+        let _ = self.try_execute(f);
+    }
+
+    /// Like `execute`, but returns an error if the job was rejected because the queue is full.
+    pub fn try_execute<F>(&self, f: F) -> Result<(), QueueFullError>
+        where F: FnOnce() + Send + 'static {
+        self.try_execute_job(Box::new(f)).map_err(|_| QueueFullError)
+    }
+
+    /// Hands the task back, if it was rejected.
+    fn try_execute_job(&self, task: Task) -> Result<(), Task> {
+        dispatch(&self.shared, task)
+    }
+
+    /// Runs `f` on the pool and returns a handle to wait for its result.
+    ///
+    /// If `f` panics, the panic is passed to the handle instead of the panic hook.
+    pub fn submit<F, T>(&self, f: F) -> JobHandle<T>
+        where F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static {
+        // There's only ever one result, so the channel never needs to hold more than that.
+        let (sender, receiver) = mpsc::sync_channel(1);
+        self.execute(move || {
+            // The handle might have been dropped already, in which case nobody wants the result.
+            let _ = sender.send(panic::catch_unwind(AssertUnwindSafe(f)));
+        });
+        JobHandle::new(receiver)
+    }
+
+    /// Runs every job on the pool and waits for all of them.
+    /// The results are in the same order as the jobs.
+    ///
+    /// Calling this from inside one of the pool's own jobs can deadlock, because the waiting job occupies a worker.
+    pub fn execute_all<I, F, T>(&self, jobs: I) -> Vec<Result<T, JobError>>
+        where I: IntoIterator<Item = F>,
+              F: FnOnce() -> T + Send + 'static,
+              T: Send + 'static {
+        // Collecting first submits everything before waiting for the first result, so the jobs run side by side.
+        let handles: Vec<_> = jobs
+            .into_iter()
+            .map(|job| self.submit(job))
+            .collect();
+        handles
+            .into_iter()
+            .map(JobHandle::join)
+            .collect()
+    }
+
+    /// Calls `f` with every item on the pool, like `Iterator::map`, and collects the results in order.
+    pub fn map<I, F, T>(&self, items: I, f: F) -> Vec<Result<T, JobError>>
+        where I: IntoIterator,
+              I::Item: Send + 'static,
+              F: Fn(I::Item) -> T + Send + Sync + 'static,
+              T: Send + 'static {
+        let f = Arc::new(f);
+        self.execute_all(items.into_iter().map(|item| {
+            let f = Arc::clone(&f);
+            move || f(item)
+        }))
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // Timers that are still pending are dropped, they'd have nobody to run them anyway.
+        self.shared.timers.stop();
+        self.shared.queue.close();
+        // A worker that dies while the others are being joined puts a replacement into the list, so this goes on until no thread is left.
+        loop {
+            let threads: Vec<_> = self.shared
+                .workers()
+                .iter_mut()
+                .filter_map(|worker| Some((worker.id, worker.thread.take()?)))
+                .collect();
+            if threads.is_empty() {
+                break;
+            }
+            for (id, thread) in threads {
+                self.shared.log(LogLevel::Debug, &format!("Shutting down worker {id}"));
+                // An error means the thread panicked, which has already been reported and can't be undone here.
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Barrier, time::Duration};
+
+    #[test]
+    fn survives_panicking_jobs() {
+        let (reports, reported) = mpsc::channel();
+        let reports = Mutex::new(reports);
+        let pool = ThreadPool::new(1).with_panic_hook(move |panic| {
+            let message = format!("{:?}: {}", panic.worker_id(), panic.message().unwrap_or_default());
+            reports.lock().unwrap().send(message).unwrap();
+        });
+        pool.execute(|| panic!("job failed"));
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(move || sender.send(thread::current().id()).unwrap());
+
+        assert_eq!("Some(0): job failed", reported.recv_timeout(Duration::from_secs(5)).unwrap());
+        receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(1, pool.size());
+    }
+
+    #[test]
+    fn replaces_workers_that_die() {
+        let pool = ThreadPool::new(1).with_panic_hook(|_| panic!("the hook failed too"));
+        let (sender, receiver) = mpsc::channel();
+        let first = sender.clone();
+        pool.execute(move || first.send(thread::current().id()).unwrap());
+        let before = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        pool.execute(|| panic!("job failed"));
+        pool.execute(move || sender.send(thread::current().id()).unwrap());
+
+        let after = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_ne!(before, after);
+        assert_eq!(1, pool.size());
+        // Joining the replaced thread must not panic.
+        drop(pool);
+    }
+
+    #[test]
+    fn turns_away_jobs_that_dont_fit() {
+        let pool = ThreadPool::new(1)
+            .with_queue_capacity(1)
+            .with_overflow_policy(OverflowPolicy::Reject);
+        let (release, blocked) = mpsc::channel::<()>();
+        let (started, has_started) = mpsc::channel();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            let _ = blocked.recv();
+        });
+        has_started.recv_timeout(Duration::from_secs(5)).unwrap();
+        let queued = pool.submit(|| "queued");
+        assert!(pool.try_execute(|| ()).is_err());
+
+        let pool = pool.with_overflow_policy(OverflowPolicy::CallerRuns);
+        let caller = thread::current().id();
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(move || sender.send(thread::current().id()).unwrap());
+        assert_eq!(caller, receiver.try_recv().unwrap());
+
+        drop(release);
+        assert_eq!("queued", queued.join().unwrap());
+    }
+
+    #[test]
+    fn grows_under_load_and_shrinks_when_idle() {
+        let pool = ThreadPool::builder()
+            .min_workers(1)
+            .max_workers(3)
+            .keep_alive(Duration::from_millis(100))
+            .thread_name("elastic")
+            .build()
+            .unwrap();
+        // The barrier only opens if all three jobs run at the same time.
+        let barrier = Arc::new(Barrier::new(3));
+        let names = pool.map(0..3, move |_| {
+            barrier.wait();
+            thread::current().name().map(String::from)
+        });
+        assert_eq!(3, pool.size());
+        assert!(names.iter().all(|name| name.as_ref().unwrap().as_deref().unwrap().starts_with("elastic-")));
+
+        thread::sleep(Duration::from_millis(500));
+        assert_eq!(1, pool.size());
+        assert_eq!(4, pool.submit(|| 2 + 2).join().unwrap());
+    }
+
+    #[test]
+    fn runs_jobs_with_work_stealing() {
+        let pool = ThreadPool::builder()
+            .max_workers(4)
+            .scheduler(Scheduler::WorkStealing)
+            .build()
+            .unwrap();
+        assert_eq!(4, pool.size());
+        let sums = pool.map(0..100u64, |number| (0..=number).sum::<u64>());
+        let expected: Vec<_> = (0..100u64).map(|number| number * (number + 1) / 2).collect();
+        assert_eq!(expected, sums.into_iter().map(Result::unwrap).collect::<Vec<_>>());
+
+        // Jobs that are still queued when the pool is dropped are finished first.
+        let (sender, receiver) = mpsc::channel();
+        for number in 0..50 {
+            let sender = sender.clone();
+            pool.execute(move || sender.send(number).unwrap());
+        }
+        drop(pool);
+        drop(sender);
+        assert_eq!(50, receiver.iter().count());
+    }
+
+    #[test]
+    fn refuses_impossible_sizes() {
+        assert!(matches!(ThreadPool::build(0), Err(PoolCreationError::NoWorkers)));
+        let result = ThreadPool::builder()
+            .min_workers(4)
+            .max_workers(2)
+            .build();
+        assert!(matches!(result, Err(PoolCreationError::InvalidBounds { min: 4, max: 2 })));
+    }
+
+    #[test]
+    fn collects_results_in_order() {
+        let pool = ThreadPool::new(4);
+        let squares: Vec<_> = pool
+            .map(0..20u64, |number| {
+                // Earlier items take longer, so they finish last.
+                thread::sleep(Duration::from_millis(20 - number));
+                number * number
+            })
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!((0..20).map(|number| number * number).collect::<Vec<_>>(), squares);
+
+        let jobs: Vec<Box<dyn FnOnce() -> &'static str + Send>> = vec![Box::new(|| "first"), Box::new(|| panic!("second")), Box::new(|| "third")];
+        let results = pool.execute_all(jobs);
+        assert_eq!("first", *results[0].as_ref().unwrap());
+        assert_eq!(Some("second"), results[1].as_ref().unwrap_err().panic_message());
+        assert_eq!("third", *results[2].as_ref().unwrap());
+    }
+
+    #[test]
+    fn counts_jobs_and_logs_panics() {
+        let (logs, logged) = mpsc::channel();
+        let logs = Mutex::new(logs);
+        let pool = ThreadPool::new(2).with_logger(move |level, message| {
+            logs.lock().unwrap().send((level, message.to_string())).unwrap();
+        });
+        let monitor = pool.monitor();
+        for _ in 0..5 {
+            pool.execute(|| thread::sleep(Duration::from_millis(1)));
+        }
+        pool.execute(|| panic!("job failed"));
+        // Dropping the pool waits for every job, and the monitor still reads the final numbers.
+        drop(pool);
+        let metrics = monitor.metrics();
+
+        assert_eq!(5, metrics.completed);
+        assert_eq!(1, metrics.panicked);
+        assert_eq!((0, 0), (metrics.queued, metrics.busy));
+        assert_eq!(6, metrics.run_time.count);
+        assert_eq!(6, metrics.wait_time.count);
+        assert!(metrics.run_time.sum >= Duration::from_millis(5));
+        let errors: Vec<_> = logged
+            .try_iter()
+            .filter(|(level, _)| *level == LogLevel::Error)
+            .collect();
+        assert_eq!(1, errors.len());
+        assert!(errors[0].1.ends_with("caught a panicking job: job failed"));
+    }
+}
@@ -0,0 +1,78 @@
+use super::{PoolCreationError, Scheduler, ThreadPool};
+use std::time::Duration;
+
+/// Configures a `ThreadPool` before its workers are started.
+///
+/// The pool starts with the minimum number of workers.
+/// Whenever a job comes in and no worker is waiting for it, another one is started, up to the maximum.
+/// Workers beyond the minimum retire after they had nothing to do for the keep-alive time.
+#[derive(Clone, Debug)]
+pub struct PoolBuilder {
+    pub(super) min_workers: usize,
+    pub(super) max_workers: Option<usize>,
+    pub(super) keep_alive: Duration,
+    pub(super) thread_name: String,
+    pub(super) stack_size: Option<usize>,
+    pub(super) scheduler: Scheduler
+}
+
+impl PoolBuilder {
+    pub fn new() -> Self {
+        PoolBuilder {
+            min_workers: 1,
+            max_workers: None,
+            keep_alive: Duration::from_secs(60),
+            thread_name: String::from("worker"),
+            stack_size: None,
+            scheduler: Scheduler::default()
+        }
+    }
+
+    /// How many workers are always kept, even if they have nothing to do. The default is 1.
+    pub fn min_workers(mut self, min_workers: usize) -> Self {
+        self.min_workers = min_workers;
+        self
+    }
+
+    /// How many workers there can be at most.
+    /// The default is the number of CPUs the machine has, but never less than the minimum.
+    pub fn max_workers(mut self, max_workers: usize) -> Self {
+        self.max_workers = Some(max_workers);
+        self
+    }
+
+    /// How long a worker beyond the minimum waits for a job before it retires. The default is 60 seconds.
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// The workers are called `{name}-{id}`, which shows up in panic messages and debuggers. The default is `worker`.
+    pub fn thread_name(mut self, name: impl Into<String>) -> Self {
+        self.thread_name = name.into();
+        self
+    }
+
+    /// The stack size of each worker in bytes. By default, Rust's default stack size for new threads is used.
+    pub fn stack_size(mut self, stack_size: usize) -> Self {
+        self.stack_size = Some(stack_size);
+        self
+    }
+
+    /// Chooses how the workers get their jobs. The default is `Scheduler::SharedQueue`.
+    pub fn scheduler(mut self, scheduler: Scheduler) -> Self {
+        self.scheduler = scheduler;
+        self
+    }
+
+    /// Starts the minimum number of workers, or the maximum for `Scheduler::WorkStealing`.
+    pub fn build(self) -> Result<ThreadPool, PoolCreationError> {
+        ThreadPool::start(self)
+    }
+}
+
+impl Default for PoolBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,116 @@
+use super::panic_message;
+use std::{
+    any::Any,
+    error::Error,
+    fmt,
+    sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError},
+    thread,
+    time::Duration
+};
+
+/// Why a job didn't produce a value.
+pub enum JobError {
+    /// The job panicked. This holds the value it panicked with.
+    Panicked(Box<dyn Any + Send>),
+    /// The job was dropped before it could run, e.g. because the pool was shutting down, or its result was already taken.
+    Cancelled
+}
+
+impl JobError {
+    /// The message that was passed to `panic!`, if the job panicked with one.
+    pub fn panic_message(&self) -> Option<&str> {
+        match self {
+            JobError::Panicked(payload) => panic_message(&**payload),
+            JobError::Cancelled => None
+        }
+    }
+}
+
+// The payload can't be printed, so `Debug` is written by hand.
+impl fmt::Debug for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobError::Panicked(_) => f.debug_tuple("Panicked").field(&self.panic_message()).finish(),
+            JobError::Cancelled => f.write_str("Cancelled")
+        }
+    }
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobError::Panicked(_) => write!(f, "job panicked: {}", self.panic_message().unwrap_or("Box<dyn Any>")),
+            JobError::Cancelled => f.write_str("job was cancelled")
+        }
+    }
+}
+
+impl Error for JobError {}
+
+/// Waits for the result of a job that was handed to `ThreadPool::submit`.
+///
+/// Dropping the handle doesn't stop the job, its result is just thrown away.
+pub struct JobHandle<T> {
+    receiver: Receiver<thread::Result<T>>
+}
+
+impl<T> JobHandle<T> {
+    pub(crate) fn new(receiver: Receiver<thread::Result<T>>) -> Self {
+        JobHandle { receiver }
+    }
+
+    /// Blocks until the job is finished and returns its result.
+    pub fn join(self) -> Result<T, JobError> {
+        match self.receiver.recv() {
+            Ok(result) => result.map_err(JobError::Panicked),
+            Err(_) => Err(JobError::Cancelled)
+        }
+    }
+
+    /// Returns the result, if the job is already finished, or `None` if it's still queued or running.
+    ///
+    /// The result can only be taken once; asking again returns `JobError::Cancelled`.
+    pub fn try_join(&mut self) -> Option<Result<T, JobError>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(result.map_err(JobError::Panicked)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(JobError::Cancelled))
+        }
+    }
+
+    /// Like `try_join`, but waits up to `timeout` for the job to finish.
+    pub fn join_timeout(&mut self, timeout: Duration) -> Option<Result<T, JobError>> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(result) => Some(result.map_err(JobError::Panicked)),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => Some(Err(JobError::Cancelled))
+        }
+    }
+}
+
+impl<T> fmt::Debug for JobHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JobHandle").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ThreadPool;
+    use std::{sync::mpsc, time::Duration};
+
+    #[test]
+    fn waits_for_results_and_reports_panics() {
+        let pool = ThreadPool::new(2);
+        let (sender, receiver) = mpsc::channel::<()>();
+        let mut blocked = pool.submit(move || receiver.recv().is_err());
+        assert!(blocked.try_join().is_none());
+        assert!(blocked.join_timeout(Duration::from_millis(50)).is_none());
+        drop(sender);
+        assert!(blocked.join_timeout(Duration::from_secs(5)).unwrap().unwrap());
+
+        let error = pool.submit(|| -> u32 { panic!("no result") }).join().unwrap_err();
+        assert_eq!(Some("no result"), error.panic_message());
+        assert_eq!(42, pool.submit(|| 6 * 7).join().unwrap());
+    }
+}
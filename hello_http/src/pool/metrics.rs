@@ -0,0 +1,129 @@
+use std::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration
+};
+
+// The upper bounds of the latency buckets, from a tenth of a millisecond to ten seconds.
+const BUCKETS: [Duration; 11] = [
+    Duration::from_micros(100),
+    Duration::from_micros(500),
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+    Duration::from_secs(10)
+];
+
+/// How many observations fell into each latency bucket.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+    /// Upper bounds with the number of observations that took at most that long, so the counts only ever go up.
+    /// Everything that took longer than the last bound is only in `count`.
+    pub buckets: Vec<(Duration, u64)>,
+    pub count: u64,
+    pub sum: Duration
+}
+
+// Lock-free counters behind a `Histogram`. Every bucket only counts its own observations; they're added up when a snapshot is taken.
+pub(crate) struct Recorder {
+    buckets: [AtomicU64; BUCKETS.len() + 1],
+    sum_nanos: AtomicU64
+}
+
+impl Recorder {
+    pub(crate) fn new() -> Self {
+        Recorder {
+            buckets: Default::default(),
+            sum_nanos: AtomicU64::new(0)
+        }
+    }
+
+    pub(crate) fn record(&self, duration: Duration) {
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| duration <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> Histogram {
+        let mut total = 0;
+        let mut buckets = Vec::with_capacity(BUCKETS.len());
+        for (bound, count) in BUCKETS.iter().zip(&self.buckets) {
+            total += count.load(Ordering::Relaxed);
+            buckets.push((*bound, total));
+        }
+        total += self.buckets[BUCKETS.len()].load(Ordering::Relaxed);
+        Histogram {
+            buckets,
+            count: total,
+            sum: Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed))
+        }
+    }
+}
+
+/// What a `ThreadPool` is doing at the moment a snapshot is taken, and what it did so far.
+///
+/// The numbers are collected without stopping the workers, so they can be off by a job or two from each other.
+#[derive(Clone, Debug, Default)]
+pub struct PoolMetrics {
+    /// Jobs waiting for a free worker.
+    pub queued: usize,
+    pub workers: usize,
+    /// Workers that are running a job right now.
+    pub busy: usize,
+    pub idle: usize,
+    /// Jobs that ran to the end, including the ones that ran on the caller's thread.
+    pub completed: u64,
+    pub panicked: u64,
+    /// How long jobs waited in the queue before a worker picked them up.
+    pub wait_time: Histogram,
+    /// How long jobs took to run.
+    pub run_time: Histogram
+}
+
+pub(crate) struct Counters {
+    pub(crate) busy: AtomicUsize,
+    pub(crate) completed: AtomicU64,
+    pub(crate) panicked: AtomicU64,
+    pub(crate) wait_time: Recorder,
+    pub(crate) run_time: Recorder
+}
+
+impl Counters {
+    pub(crate) fn new() -> Self {
+        Counters {
+            busy: AtomicUsize::new(0),
+            completed: AtomicU64::new(0),
+            panicked: AtomicU64::new(0),
+            wait_time: Recorder::new(),
+            run_time: Recorder::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_observations_per_bucket() {
+        let recorder = Recorder::new();
+        recorder.record(Duration::from_micros(50));
+        recorder.record(Duration::from_millis(1));
+        recorder.record(Duration::from_secs(60));
+        let histogram = recorder.snapshot();
+
+        assert_eq!((Duration::from_micros(100), 1), histogram.buckets[0]);
+        assert_eq!((Duration::from_millis(1), 2), histogram.buckets[2]);
+        assert_eq!((Duration::from_secs(10), 2), histogram.buckets[10]);
+        assert_eq!(3, histogram.count);
+        assert_eq!(Duration::from_micros(60_001_050), histogram.sum);
+    }
+}
@@ -0,0 +1,232 @@
+use super::Job;
+use std::{
+    collections::VecDeque,
+    sync::{Condvar, Mutex, MutexGuard, PoisonError},
+    time::Duration
+};
+
+/// What happens to a job that is handed to a pool whose queue is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Waits until a worker takes a job off the queue.
+    /// Doing this from inside one of the pool's own jobs can deadlock.
+    #[default]
+    Block,
+    /// Refuses the job, so the caller can tell someone to come back later.
+    Reject,
+    /// Throws away the job that has been waiting the longest, to make room for the new one.
+    DropOldest,
+    /// Runs the job right away on the thread that handed it over, which naturally slows the caller down.
+    CallerRuns
+}
+
+pub(crate) enum Overflow {
+    Rejected(Job),
+    RunHere(Job)
+}
+
+struct State {
+    jobs: VecDeque<Job>,
+    // `None` means the queue can grow as long as there's memory.
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    closed: bool,
+    // The workers are counted here, so that starting and retiring them is decided under the same lock as taking jobs.
+    workers: usize,
+    idle: usize,
+    // Waking up a condition variable costs a system call, so it's only done when somebody is waiting.
+    blocked: usize
+}
+
+impl State {
+    fn is_full(&self) -> bool {
+        self.capacity.is_some_and(|capacity| self.jobs.len() >= capacity)
+    }
+}
+
+/// The jobs that wait for a free worker, and the bookkeeping for how many workers there should be.
+pub(crate) struct Queue {
+    state: Mutex<State>,
+    min_workers: usize,
+    max_workers: usize,
+    keep_alive: Duration,
+    // Workers wait on this one for jobs, blocked callers on the other one for space.
+    available: Condvar,
+    space: Condvar
+}
+
+impl Queue {
+    /// Creates a queue that counts `min_workers` workers, which the pool is about to start.
+    pub(crate) fn new(min_workers: usize, max_workers: usize, keep_alive: Duration) -> Self {
+        Queue {
+            state: Mutex::new(State {
+                jobs: VecDeque::new(),
+                capacity: None,
+                policy: OverflowPolicy::default(),
+                closed: false,
+                workers: min_workers,
+                idle: 0,
+                blocked: 0
+            }),
+            min_workers,
+            max_workers,
+            keep_alive,
+            available: Condvar::new(),
+            space: Condvar::new()
+        }
+    }
+
+    pub(crate) fn set_capacity(&self, capacity: Option<usize>) {
+        self.lock().capacity = capacity;
+        // The queue might have more room now.
+        self.space.notify_all();
+    }
+
+    pub(crate) fn set_policy(&self, policy: OverflowPolicy) {
+        self.lock().policy = policy;
+        self.space.notify_all();
+    }
+
+    /// Adds a job to the end of the queue, or hands it back if the policy says it doesn't go in.
+    pub(crate) fn push(&self, job: Job) -> Result<(), Overflow> {
+        let mut state = self.lock();
+        while state.is_full() {
+            match state.policy {
+                OverflowPolicy::Block => {
+                    state.blocked += 1;
+                    state = self.space
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner);
+                    state.blocked -= 1;
+                }
+                OverflowPolicy::Reject => return Err(Overflow::Rejected(job)),
+                OverflowPolicy::DropOldest => {
+                    let oldest = state.jobs.pop_front();
+                    state.jobs.push_back(job);
+                    drop(state);
+                    // Dropping a job can run arbitrary code, e.g. closing a connection, so it happens outside the lock.
+                    drop(oldest);
+                    return Ok(());
+                }
+                OverflowPolicy::CallerRuns => return Err(Overflow::RunHere(job))
+            }
+        }
+        state.jobs.push_back(job);
+        let wake = state.idle > 0;
+        drop(state);
+        if wake {
+            self.available.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Takes the next job, waiting for one if necessary.
+    /// Returns `None` once the queue is closed and every job has been taken, or if the worker should retire because it had nothing to do for too long.
+    pub(crate) fn pop(&self) -> Option<Job> {
+        let mut state = self.lock();
+        loop {
+            if let Some(job) = state.jobs.pop_front() {
+                let wake = state.blocked > 0;
+                drop(state);
+                if wake {
+                    self.space.notify_one();
+                }
+                return Some(job);
+            }
+            if state.closed {
+                return None;
+            }
+            state.idle += 1;
+            let (next, timeout) = self.available
+                .wait_timeout(state, self.keep_alive)
+                .unwrap_or_else(PoisonError::into_inner);
+            state = next;
+            state.idle -= 1;
+            if timeout.timed_out() && state.jobs.is_empty() && !state.closed && state.workers > self.min_workers {
+                state.workers -= 1;
+                return None;
+            }
+        }
+    }
+
+    /// Counts one more worker, if a new job wouldn't find an idle one and there's still room below the maximum.
+    /// Returns `true` if the caller has to start that worker.
+    pub(crate) fn reserve_worker(&self) -> bool {
+        // A pool of fixed size doesn't need to lock the queue just to find that out.
+        if self.min_workers == self.max_workers {
+            return false;
+        }
+        let mut state = self.lock();
+        let needed = state.jobs.len() >= state.idle && state.workers < self.max_workers;
+        if needed {
+            state.workers += 1;
+        }
+        needed
+    }
+
+    /// Gives back a place reserved with `reserve_worker`, e.g. because the thread couldn't be started.
+    pub(crate) fn release_worker(&self) {
+        self.lock().workers -= 1;
+    }
+
+    pub(crate) fn workers(&self) -> usize {
+        self.lock().workers
+    }
+
+    pub(crate) fn queued(&self) -> usize {
+        self.lock().jobs.len()
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.lock().closed
+    }
+
+    /// Lets the workers finish what's left and then stop.
+    pub(crate) fn close(&self) {
+        self.lock().closed = true;
+        self.available.notify_all();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // A mutex might be in a so-called "poisoned" state, if another thread that holds it panicked and didn't release it.
+        // Jobs never run while the queue is locked, so the poison would be harmless anyway.
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+
+    fn counting_job(counter: &Arc<AtomicUsize>) -> Job {
+        let counter = Arc::clone(counter);
+        Job::new(Box::new(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        }))
+    }
+
+    #[test]
+    fn applies_the_overflow_policy() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let queue = Queue::new(1, 1, Duration::from_secs(60));
+        queue.set_capacity(Some(1));
+        queue.set_policy(OverflowPolicy::Reject);
+        assert!(queue.push(counting_job(&counter)).is_ok());
+        assert!(matches!(queue.push(counting_job(&counter)), Err(Overflow::Rejected(_))));
+
+        queue.set_policy(OverflowPolicy::CallerRuns);
+        assert!(matches!(queue.push(counting_job(&counter)), Err(Overflow::RunHere(_))));
+
+        queue.set_policy(OverflowPolicy::DropOldest);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        assert!(queue.push(counting_job(&dropped)).is_ok());
+        queue.close();
+        (queue.pop().unwrap().task)();
+        assert!(queue.pop().is_none());
+        assert_eq!(1, dropped.load(Ordering::SeqCst));
+        assert_eq!(0, counter.load(Ordering::SeqCst));
+    }
+}
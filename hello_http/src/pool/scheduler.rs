@@ -0,0 +1,132 @@
+use super::{
+    queue::{Overflow, Queue},
+    stealing::Stealing,
+    Job,
+    OverflowPolicy
+};
+use crossbeam_deque::Worker as Deque;
+use std::time::Duration;
+
+/// How the workers of a `ThreadPool` get their jobs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scheduler {
+    /// All workers take jobs from one queue behind a mutex.
+    /// This is the only scheduler that can grow and shrink the pool.
+    #[default]
+    SharedQueue,
+    /// Every worker has its own deque and steals from the others when it runs dry.
+    /// This scales better with many workers and tiny jobs, but the pool always runs with its maximum number of workers.
+    WorkStealing
+}
+
+pub(crate) enum Backend {
+    SharedQueue(Queue),
+    // The injector and the padded counters make this variant a lot bigger than the other one.
+    WorkStealing(Box<Stealing>)
+}
+
+impl Backend {
+    pub(crate) fn new(scheduler: Scheduler, min_workers: usize, max_workers: usize, keep_alive: Duration) -> Self {
+        match scheduler {
+            Scheduler::SharedQueue => Backend::SharedQueue(Queue::new(min_workers, max_workers, keep_alive)),
+            Scheduler::WorkStealing => Backend::WorkStealing(Box::new(Stealing::new(max_workers)))
+        }
+    }
+
+    /// Prepares what a worker needs to take jobs. It has to be dropped on the worker's thread, once the worker stops.
+    pub(crate) fn local(&self, id: usize) -> Local<'_> {
+        let deque = match self {
+            Backend::SharedQueue(_) => None,
+            Backend::WorkStealing(stealing) => stealing.take_deque(id)
+        };
+        Local { backend: self, id, deque }
+    }
+
+    pub(crate) fn set_capacity(&self, capacity: Option<usize>) {
+        match self {
+            Backend::SharedQueue(queue) => queue.set_capacity(capacity),
+            Backend::WorkStealing(stealing) => stealing.set_capacity(capacity)
+        }
+    }
+
+    pub(crate) fn set_policy(&self, policy: OverflowPolicy) {
+        match self {
+            Backend::SharedQueue(queue) => queue.set_policy(policy),
+            Backend::WorkStealing(stealing) => stealing.set_policy(policy)
+        }
+    }
+
+    pub(crate) fn push(&self, job: Job) -> Result<(), Overflow> {
+        match self {
+            Backend::SharedQueue(queue) => queue.push(job),
+            Backend::WorkStealing(stealing) => stealing.push(job)
+        }
+    }
+
+    pub(crate) fn pop(&self, local: &Local<'_>) -> Option<Job> {
+        match (self, &local.deque) {
+            (Backend::SharedQueue(queue), _) => queue.pop(),
+            (Backend::WorkStealing(stealing), Some(deque)) => stealing.pop(deque),
+            // Every worker id gets a deque, so this can't happen.
+            (Backend::WorkStealing(_), None) => None
+        }
+    }
+
+    pub(crate) fn reserve_worker(&self) -> bool {
+        match self {
+            Backend::SharedQueue(queue) => queue.reserve_worker(),
+            Backend::WorkStealing(_) => false
+        }
+    }
+
+    pub(crate) fn release_worker(&self) {
+        match self {
+            Backend::SharedQueue(queue) => queue.release_worker(),
+            Backend::WorkStealing(stealing) => stealing.release_worker()
+        }
+    }
+
+    pub(crate) fn workers(&self) -> usize {
+        match self {
+            Backend::SharedQueue(queue) => queue.workers(),
+            Backend::WorkStealing(stealing) => stealing.workers()
+        }
+    }
+
+    pub(crate) fn queued(&self) -> usize {
+        match self {
+            Backend::SharedQueue(queue) => queue.queued(),
+            Backend::WorkStealing(stealing) => stealing.queued()
+        }
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        match self {
+            Backend::SharedQueue(queue) => queue.is_closed(),
+            Backend::WorkStealing(stealing) => stealing.is_closed()
+        }
+    }
+
+    pub(crate) fn close(&self) {
+        match self {
+            Backend::SharedQueue(queue) => queue.close(),
+            Backend::WorkStealing(stealing) => stealing.close()
+        }
+    }
+}
+
+/// What a worker keeps for itself while it runs.
+pub(crate) struct Local<'a> {
+    backend: &'a Backend,
+    id: usize,
+    deque: Option<Deque<Job>>
+}
+
+impl Drop for Local<'_> {
+    fn drop(&mut self) {
+        // This also runs when the worker unwinds, so the jobs in its deque aren't lost.
+        if let (Backend::WorkStealing(stealing), Some(deque)) = (self.backend, self.deque.take()) {
+            stealing.return_deque(self.id, deque);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use super::ThreadPool;
+use std::{
+    any::Any,
+    marker::PhantomData,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError}
+};
+
+struct State {
+    pending: Mutex<usize>,
+    finished: Condvar,
+    // Only the first panic is passed on; the others are dropped, just like `std::thread::scope` does.
+    panic: Mutex<Option<Box<dyn Any + Send>>>
+}
+
+impl State {
+    fn pending(&self) -> MutexGuard<'_, usize> {
+        self.pending
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn record_panic(&self, payload: Box<dyn Any + Send>) {
+        self.panic
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_or_insert(payload);
+    }
+
+    fn wait(&self) {
+        let mut pending = self.pending();
+        while *pending > 0 {
+            pending = self.finished
+                .wait(pending)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+/// Lets jobs borrow from the stack of the thread that called `ThreadPool::scope`.
+///
+/// The lifetimes work like the ones of `std::thread::Scope`:
+/// `'env` is what the jobs may borrow, and `'scope` is how long the scope lives.
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope ThreadPool,
+    state: Arc<State>,
+    // Both lifetimes have to be invariant, otherwise the compiler could shorten them and let a job outlive what it borrows.
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>
+}
+
+impl<'scope> Scope<'scope, '_> {
+    /// Runs `f` on one of the pool's workers. `f` may borrow anything that outlives the scope.
+    ///
+    /// If the pool turns the job away because its queue is full, it runs on the calling thread instead.
+    pub fn spawn<F>(&'scope self, f: F)
+        where F: FnOnce() + Send + 'scope {
+        *self.state.pending() += 1;
+        let running = Running {
+            f: Some(f),
+            state: Arc::clone(&self.state)
+        };
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || running.run());
+        // SAFETY: The pool wants jobs that can live forever, but this one only lives for `'scope`.
+        // `ThreadPool::scope` doesn't return before `pending` is back to zero, which only happens once the job has run or was dropped,
+        // so nothing the job borrows can go away while the job still exists.
+        let job: Box<dyn FnOnce() + Send + 'static> = unsafe { mem::transmute(job) };
+        if let Err(rejected) = self.pool.try_execute_job(job) {
+            rejected();
+        }
+    }
+}
+
+// Keeps track of a scoped job, no matter if it runs, panics or is dropped by an overflow policy.
+struct Running<F>
+    where F: FnOnce() {
+    f: Option<F>,
+    state: Arc<State>
+}
+
+impl<F> Running<F>
+    where F: FnOnce() {
+    fn run(mut self) {
+        if let Some(f) = self.f.take() {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+                self.state.record_panic(payload);
+            }
+        }
+    }
+}
+
+impl<F> Drop for Running<F>
+    where F: FnOnce() {
+    fn drop(&mut self) {
+        // The job has to be gone before it's counted as finished, because dropping it might still touch what it borrowed.
+        if let Some(f) = self.f.take() {
+            drop(f);
+            self.state.record_panic(Box::new("a scoped job was dropped before it could run"));
+        }
+        let mut pending = self.state.pending();
+        *pending -= 1;
+        if *pending == 0 {
+            self.state.finished.notify_all();
+        }
+    }
+}
+
+impl ThreadPool {
+    /// Runs `f`, which can spawn jobs on this pool that borrow from the caller, like `std::thread::scope` does with threads.
+    ///
+    /// All jobs are finished before this returns.
+    /// If `f` or one of the jobs panicked, the panic is passed on to the caller once everything is finished.
+    ///
+    /// Calling this from inside one of the pool's own jobs can deadlock, because the waiting job occupies a worker.
+    pub fn scope<'env, F, T>(&self, f: F) -> T
+        where F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T {
+        let scope = Scope {
+            pool: self,
+            state: Arc::new(State {
+                pending: Mutex::new(0),
+                finished: Condvar::new(),
+                panic: Mutex::new(None)
+            }),
+            scope: PhantomData,
+            env: PhantomData
+        };
+        // Even if `f` panics, the jobs it spawned still borrow from the stack, so they have to be waited for before unwinding any further.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.state.wait();
+
+        let job_panic = scope.state.panic
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        match (result, job_panic) {
+            (Err(payload), _) | (Ok(_), Some(payload)) => panic::resume_unwind(payload),
+            (Ok(result), None) => result
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{pool::panic_message, OverflowPolicy, ThreadPool};
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        sync::atomic::{AtomicUsize, Ordering}
+    };
+
+    #[test]
+    fn jobs_borrow_from_the_caller() {
+        let pool = ThreadPool::new(3);
+        let numbers: Vec<u64> = (1..=100).collect();
+        let mut sums = [0; 4];
+        let spawned = AtomicUsize::new(0);
+        pool.scope(|scope| {
+            for (chunk, sum) in numbers.chunks(25).zip(sums.iter_mut()) {
+                scope.spawn(|| *sum = chunk.iter().sum());
+            }
+            // Jobs can start more jobs in the same scope.
+            scope.spawn(|| scope.spawn(|| {
+                spawned.fetch_add(1, Ordering::SeqCst);
+            }));
+        });
+
+        assert_eq!(5050, sums.iter().sum::<u64>());
+        assert_eq!(1, spawned.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn passes_panics_on_after_every_job_finished() {
+        // Jobs that don't fit into the queue run on the calling thread instead of getting lost.
+        let pool = ThreadPool::new(2)
+            .with_queue_capacity(1)
+            .with_overflow_policy(OverflowPolicy::Reject);
+        let finished = AtomicUsize::new(0);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|scope| {
+                scope.spawn(|| panic!("scoped job failed"));
+                for _ in 0..10 {
+                    scope.spawn(|| {
+                        finished.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            })
+        }));
+
+        assert_eq!(Some("scoped job failed"), panic_message(&*result.unwrap_err()));
+        assert_eq!(10, finished.load(Ordering::SeqCst));
+    }
+}
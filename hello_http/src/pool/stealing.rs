@@ -0,0 +1,303 @@
+use super::{queue::Overflow, Job, OverflowPolicy};
+use crossbeam_deque::{Injector, Steal, Stealer, Worker as Deque};
+use std::{
+    iter,
+    sync::{
+        atomic::{fence, AtomicBool, AtomicUsize, Ordering},
+        Condvar,
+        Mutex,
+        MutexGuard,
+        PoisonError
+    },
+    time::Duration
+};
+
+// Callers waiting for room in a full queue look again this often, even if no worker told them about it.
+const NAP: Duration = Duration::from_millis(20);
+
+/// Jobs go into a global injector queue, from which the workers take them in batches into their own deques.
+/// A worker that runs out of jobs steals from the others, so nobody has to wait for a lock just to get the next job.
+///
+/// This scheduler keeps a fixed number of workers; there's no growing or shrinking.
+pub(crate) struct Stealing {
+    injector: Injector<Job>,
+    stealers: Vec<Stealer<Job>>,
+    // Every worker borrows its deque while it runs and gives it back when it stops, so a replacement can pick up the jobs that were left in it.
+    deques: Mutex<Vec<Option<Deque<Job>>>>,
+    workers: AtomicUsize,
+    queued: AtomicUsize,
+    // `usize::MAX` means the queue can grow as long as there's memory.
+    capacity: AtomicUsize,
+    policy: Mutex<OverflowPolicy>,
+    closed: AtomicBool,
+    // The atomic counters let the hot path skip the lock when nobody is sleeping or blocked.
+    sleeping: AtomicUsize,
+    blocked: AtomicUsize,
+    lock: Mutex<()>,
+    wake: Condvar,
+    space: Condvar
+}
+
+impl Stealing {
+    pub(crate) fn new(workers: usize) -> Self {
+        // FIFO deques keep the jobs roughly in the order they were handed over.
+        let deques: Vec<_> = (0..workers).map(|_| Deque::new_fifo()).collect();
+        Stealing {
+            injector: Injector::new(),
+            stealers: deques.iter().map(Deque::stealer).collect(),
+            deques: Mutex::new(deques.into_iter().map(Some).collect()),
+            workers: AtomicUsize::new(workers),
+            queued: AtomicUsize::new(0),
+            capacity: AtomicUsize::new(usize::MAX),
+            policy: Mutex::new(OverflowPolicy::default()),
+            closed: AtomicBool::new(false),
+            sleeping: AtomicUsize::new(0),
+            blocked: AtomicUsize::new(0),
+            lock: Mutex::new(()),
+            wake: Condvar::new(),
+            space: Condvar::new()
+        }
+    }
+
+    pub(crate) fn set_capacity(&self, capacity: Option<usize>) {
+        self.capacity.store(capacity.unwrap_or(usize::MAX), Ordering::SeqCst);
+        let _guard = self.lock();
+        self.space.notify_all();
+    }
+
+    pub(crate) fn set_policy(&self, policy: OverflowPolicy) {
+        *self.policy.lock().unwrap_or_else(PoisonError::into_inner) = policy;
+        let _guard = self.lock();
+        self.space.notify_all();
+    }
+
+    pub(crate) fn take_deque(&self, id: usize) -> Option<Deque<Job>> {
+        self.deques().get_mut(id)?.take()
+    }
+
+    pub(crate) fn return_deque(&self, id: usize, deque: Deque<Job>) {
+        if let Some(slot) = self.deques().get_mut(id) {
+            *slot = Some(deque);
+        }
+    }
+
+    pub(crate) fn push(&self, job: Job) -> Result<(), Overflow> {
+        loop {
+            let capacity = self.capacity.load(Ordering::SeqCst);
+            let queued = self.queued.load(Ordering::SeqCst);
+            if queued < capacity {
+                if self.queued.compare_exchange_weak(queued, queued + 1, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                    break;
+                }
+                continue;
+            }
+            let policy = *self.policy.lock().unwrap_or_else(PoisonError::into_inner);
+            match policy {
+                OverflowPolicy::Block => self.wait_for_space(),
+                OverflowPolicy::Reject => return Err(Overflow::Rejected(job)),
+                OverflowPolicy::DropOldest => {
+                    // Only jobs in the injector can be dropped. Once a worker took a batch into its deque, they're going to run.
+                    let oldest = iter::repeat_with(|| self.injector.steal())
+                        .find(|steal| !steal.is_retry())
+                        .and_then(Steal::success);
+                    if let Some(oldest) = oldest {
+                        // The new job takes the place of the old one, so `queued` stays the same.
+                        self.injector.push(job);
+                        self.wake_one();
+                        drop(oldest);
+                        return Ok(());
+                    }
+                    // All the queued jobs are in deques already, so one of them is about to start and make room.
+                    self.wait_for_space();
+                }
+                OverflowPolicy::CallerRuns => return Err(Overflow::RunHere(job))
+            }
+        }
+        self.injector.push(job);
+        self.wake_one();
+        Ok(())
+    }
+
+    fn wait_for_space(&self) {
+        let guard = self.lock();
+        self.blocked.fetch_add(1, Ordering::SeqCst);
+        // Checking again after announcing the wait means a worker that takes a job now is sure to see the announcement.
+        if self.queued.load(Ordering::SeqCst) >= self.capacity.load(Ordering::SeqCst) {
+            let _ = self.space
+                .wait_timeout(guard, NAP)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        self.blocked.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn wake_one(&self) {
+        // The fence pairs with the one in `pop`, so either the worker sees the new job, or this sees the sleeping worker.
+        fence(Ordering::SeqCst);
+        if self.sleeping.load(Ordering::SeqCst) > 0 {
+            let _guard = self.lock();
+            self.wake.notify_one();
+        }
+    }
+
+    pub(crate) fn pop(&self, deque: &Deque<Job>) -> Option<Job> {
+        loop {
+            if let Some(job) = self.find(deque) {
+                self.queued.fetch_sub(1, Ordering::SeqCst);
+                if self.blocked.load(Ordering::SeqCst) > 0 {
+                    let _guard = self.lock();
+                    self.space.notify_all();
+                }
+                // Jobs that were taken into a deque in a batch don't wake anybody, so the wake-up is passed on while there are some left.
+                if self.stealers.iter().any(|stealer| !stealer.is_empty()) {
+                    self.wake_one();
+                }
+                return Some(job);
+            }
+            // Checking for the close only when nothing was found lets the workers finish what's left first.
+            if self.closed.load(Ordering::SeqCst) {
+                return None;
+            }
+            let guard = self.lock();
+            self.sleeping.fetch_add(1, Ordering::SeqCst);
+            // The fence pairs with the one in `wake_one`, see there.
+            fence(Ordering::SeqCst);
+            if !self.has_jobs() && !self.closed.load(Ordering::SeqCst) {
+                let _guard = self.wake
+                    .wait(guard)
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+            self.sleeping.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    fn has_jobs(&self) -> bool {
+        !self.injector.is_empty() || self.stealers.iter().any(|stealer| !stealer.is_empty())
+    }
+
+    fn find(&self, deque: &Deque<Job>) -> Option<Job> {
+        // This is the order crossbeam's documentation suggests: the own deque, then a batch from the injector, then the other workers.
+        // `Steal::Retry` means another thread got in the way, so it's tried again.
+        deque.pop().or_else(|| {
+            iter::repeat_with(|| {
+                self.injector
+                    .steal_batch_and_pop(deque)
+                    .or_else(|| self.stealers.iter().map(Stealer::steal).collect())
+            })
+            .find(|steal| !steal.is_retry())
+            .and_then(Steal::success)
+        })
+    }
+
+    pub(crate) fn release_worker(&self) {
+        self.workers.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn workers(&self) -> usize {
+        self.workers.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        let _guard = self.lock();
+        self.wake.notify_all();
+    }
+
+    fn deques(&self) -> MutexGuard<'_, Vec<Option<Deque<Job>>>> {
+        self.deques
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ()> {
+        self.lock
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn steals_jobs_from_other_deques() {
+        let stealing = Stealing::new(2);
+        let first = stealing.take_deque(0).unwrap();
+        let second = stealing.take_deque(1).unwrap();
+        let ran = Arc::new(AtomicUsize::new(0));
+        for _ in 0..4 {
+            let ran = Arc::clone(&ran);
+            assert!(stealing.push(Job::new(Box::new(move || {
+                ran.fetch_add(1, Ordering::SeqCst);
+            }))).is_ok());
+        }
+
+        // The first worker takes a batch into its deque, the second one has to steal from there.
+        (stealing.pop(&first).unwrap().task)();
+        assert!(!first.is_empty());
+        stealing.close();
+        while let Some(job) = stealing.pop(&second) {
+            (job.task)();
+        }
+        assert_eq!(4, ran.load(Ordering::SeqCst));
+        assert!(stealing.pop(&first).is_none());
+    }
+
+    #[test]
+    fn drops_the_oldest_jobs_without_going_over_capacity() {
+        let stealing = Arc::new(Stealing::new(1));
+        let deque = stealing.take_deque(0).unwrap();
+        let ran = Arc::new(AtomicUsize::new(0));
+        let counting_job = || {
+            let ran = Arc::clone(&ran);
+            Job::new(Box::new(move || {
+                ran.fetch_add(1, Ordering::SeqCst);
+            }))
+        };
+        stealing.set_capacity(Some(2));
+        stealing.set_policy(OverflowPolicy::DropOldest);
+
+        for _ in 0..4 {
+            assert!(stealing.push(counting_job()).is_ok());
+            assert!(stealing.queued() <= 2);
+        }
+        assert_eq!(2, stealing.queued());
+
+        while stealing.queued() > 0 {
+            (stealing.pop(&deque).unwrap().task)();
+        }
+
+        // Once a worker took the jobs into its deque, they can't be dropped, so the caller waits until one of them started.
+        for _ in 0..2 {
+            deque.push(counting_job());
+            stealing.queued.fetch_add(1, Ordering::SeqCst);
+        }
+        let pusher = {
+            let stealing = Arc::clone(&stealing);
+            let job = counting_job();
+            thread::spawn(move || stealing.push(job).is_ok())
+        };
+        thread::sleep(Duration::from_millis(100));
+        assert!(!pusher.is_finished());
+        assert_eq!(2, stealing.queued());
+        (stealing.pop(&deque).unwrap().task)();
+        assert!(pusher.join().unwrap());
+        assert_eq!(2, stealing.queued());
+
+        stealing.close();
+        while let Some(job) = stealing.pop(&deque) {
+            (job.task)();
+        }
+        assert_eq!(0, stealing.queued());
+        assert_eq!(5, ran.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,463 @@
+use super::{dispatch, LogLevel, Shared, Task, ThreadPool};
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    io,
+    sync::{
+        atomic::{self, AtomicBool},
+        Arc,
+        Condvar,
+        Mutex,
+        MutexGuard,
+        PoisonError,
+        RwLock,
+        Weak
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant}
+};
+
+/// Tells the timers of a `ThreadPool` what time it is.
+///
+/// The pool uses `SystemClock` unless another one is passed to `ThreadPool::with_clock`, e.g. a `ManualClock` in tests.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Gives the clock a way to wake the timers up, in case its time can jump ahead.
+    /// Clocks that just follow real time can ignore it, which is what the default does.
+    fn on_change(&self, _wake: Box<dyn Fn() + Send + Sync>) {}
+}
+
+/// The real time, as told by `Instant::now`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+struct ManualTime {
+    now: Mutex<Instant>,
+    wakers: Mutex<Vec<Box<dyn Fn() + Send + Sync>>>
+}
+
+/// A clock that only moves when it's told to, so tests don't have to wait for timers.
+///
+/// Clones share the same time, so one clone can be given to the pool and another one kept to call `advance`.
+#[derive(Clone)]
+pub struct ManualClock {
+    time: Arc<ManualTime>
+}
+
+impl ManualClock {
+    /// Starts at the current time.
+    pub fn new() -> Self {
+        ManualClock {
+            time: Arc::new(ManualTime {
+                now: Mutex::new(Instant::now()),
+                wakers: Mutex::new(Vec::new())
+            })
+        }
+    }
+
+    /// Moves the time forward, which runs every timer that is due by then.
+    pub fn advance(&self, by: Duration) {
+        *self.time.now.lock().unwrap_or_else(PoisonError::into_inner) += by;
+        // The time isn't locked anymore, because the timers read it while they hold their own lock.
+        for wake in self.time.wakers.lock().unwrap_or_else(PoisonError::into_inner).iter() {
+            wake();
+        }
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.time.now.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn on_change(&self, wake: Box<dyn Fn() + Send + Sync>) {
+        self.time.wakers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(wake);
+    }
+}
+
+/// Cancels a scheduled job.
+///
+/// Dropping the handle doesn't cancel anything, the job just can't be stopped anymore, like a detached thread.
+#[derive(Clone, Debug)]
+pub struct TimerHandle {
+    cancelled: Arc<AtomicBool>
+}
+
+impl TimerHandle {
+    /// Stops the job from running again. A run that already started is finished.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, atomic::Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(atomic::Ordering::SeqCst)
+    }
+}
+
+enum Kind {
+    Once(Task),
+    Every {
+        interval: Duration,
+        job: Arc<dyn Fn() + Send + Sync>,
+        // A run that takes longer than the interval makes the next ones skip, instead of piling up on the workers.
+        running: Arc<AtomicBool>
+    }
+}
+
+struct Entry {
+    deadline: Instant,
+    // Timers with the same deadline run in the order they were scheduled.
+    sequence: u64,
+    cancelled: Arc<AtomicBool>,
+    kind: Kind
+}
+
+// `BinaryHeap` is a max-heap, so the order is reversed to get the earliest deadline first.
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.deadline, other.sequence).cmp(&(self.deadline, self.sequence))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+struct State {
+    entries: BinaryHeap<Entry>,
+    next_sequence: u64,
+    stopped: bool
+}
+
+/// The timers of one pool. A single thread waits for the next deadline and hands the due jobs to the workers.
+pub(crate) struct Timers {
+    state: Mutex<State>,
+    changed: Condvar,
+    clock: RwLock<Arc<dyn Clock>>,
+    // The thread is only started once the first timer is scheduled.
+    thread: Mutex<Option<JoinHandle<()>>>
+}
+
+impl Timers {
+    pub(crate) fn new() -> Self {
+        Timers {
+            state: Mutex::new(State {
+                entries: BinaryHeap::new(),
+                next_sequence: 0,
+                stopped: false
+            }),
+            changed: Condvar::new(),
+            clock: RwLock::new(Arc::new(SystemClock)),
+            thread: Mutex::new(None)
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn now(&self) -> Instant {
+        self.clock
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .now()
+    }
+
+    fn wake(&self) {
+        // Taking the lock makes sure the timer thread is either waiting already or is going to see the change.
+        let _state = self.lock();
+        self.changed.notify_all();
+    }
+
+    fn set_clock(self: &Arc<Self>, clock: Arc<dyn Clock>) {
+        let timers = Arc::downgrade(self);
+        clock.on_change(Box::new(move || {
+            if let Some(timers) = Weak::upgrade(&timers) {
+                timers.wake();
+            }
+        }));
+        *self.clock.write().unwrap_or_else(PoisonError::into_inner) = clock;
+        self.wake();
+    }
+
+    fn schedule(&self, shared: &Arc<Shared>, deadline: Instant, kind: Kind) -> io::Result<TimerHandle> {
+        self.start(shared)?;
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let mut state = self.lock();
+        let sequence = state.next_sequence;
+        state.next_sequence += 1;
+        state.entries.push(Entry {
+            deadline,
+            sequence,
+            cancelled: Arc::clone(&cancelled),
+            kind
+        });
+        self.changed.notify_all();
+        Ok(TimerHandle { cancelled })
+    }
+
+    // The timer thread is started with the first timer, so pools that don't use timers don't pay for it.
+    // If it can't be started, the next timer tries again.
+    fn start(&self, shared: &Arc<Shared>) -> io::Result<()> {
+        let mut thread = self.thread.lock().unwrap_or_else(PoisonError::into_inner);
+        if thread.is_some() {
+            return Ok(());
+        }
+        let timer_shared = Arc::clone(shared);
+        let handle = thread::Builder::new()
+            .name(format!("{}-timer", shared.thread_name))
+            .spawn(move || run(&timer_shared))?;
+        *thread = Some(handle);
+        Ok(())
+    }
+
+    pub(crate) fn stop(&self) {
+        self.lock().stopped = true;
+        self.changed.notify_all();
+        let thread = self.thread
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(thread) = thread {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(shared: &Arc<Shared>) {
+    let timers = &shared.timers;
+    let mut state = timers.lock();
+    loop {
+        if state.stopped {
+            return;
+        }
+        let now = timers.now();
+        let Some(next) = state.entries.peek() else {
+            state = timers.changed
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+            continue;
+        };
+        if next.deadline > now {
+            // A clock that jumps ahead wakes the thread, so waiting the real time until the deadline is fine for every clock.
+            let timeout = next.deadline - now;
+            state = timers.changed
+                .wait_timeout(state, timeout)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+            continue;
+        }
+
+        let entry = state.entries.pop().expect("an entry was just peeked at");
+        if entry.cancelled.load(atomic::Ordering::SeqCst) {
+            continue;
+        }
+        // Pushing the job can block if the queue is full, so new timers shouldn't have to wait for that.
+        drop(state);
+        let again = fire(shared, entry, now);
+        state = timers.lock();
+        if let Some(entry) = again {
+            state.entries.push(entry);
+        }
+    }
+}
+
+/// Hands a due job to the workers and returns the entry again, if it repeats.
+fn fire(shared: &Arc<Shared>, entry: Entry, now: Instant) -> Option<Entry> {
+    match entry.kind {
+        Kind::Once(task) => {
+            submit(shared, task);
+            None
+        }
+        Kind::Every { interval, job, running } => {
+            if !running.swap(true, atomic::Ordering::SeqCst) {
+                let guard = Running(Arc::clone(&running));
+                let job = Arc::clone(&job);
+                submit(shared, Box::new(move || {
+                    let _guard = guard;
+                    job();
+                }));
+            }
+            Some(Entry {
+                deadline: next_deadline(entry.deadline, interval, now),
+                kind: Kind::Every { interval, job, running },
+                ..entry
+            })
+        }
+    }
+}
+
+fn submit(shared: &Arc<Shared>, task: Task) {
+    if dispatch(shared, task).is_err() {
+        shared.log(LogLevel::Warn, "A timer job was dropped, because the queue is full.");
+    }
+}
+
+// Clears the flag of a repeating job once it's done, even if it panicked or never got to run.
+struct Running(Arc<AtomicBool>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.store(false, atomic::Ordering::SeqCst);
+    }
+}
+
+/// Keeps repeating jobs on their original beat, but skips the runs that were missed, e.g. because the machine was asleep.
+fn next_deadline(deadline: Instant, interval: Duration, now: Instant) -> Instant {
+    let next = deadline + interval;
+    if next > now {
+        return next;
+    }
+    let missed = (now - deadline).as_nanos() / interval.as_nanos();
+    let skip = u64::try_from((missed + 1) * interval.as_nanos()).unwrap_or(u64::MAX);
+    deadline + Duration::from_nanos(skip)
+}
+
+impl ThreadPool {
+    /// Lets the timers run on another clock, e.g. a `ManualClock` to test scheduled jobs without waiting.
+    pub fn with_clock(self, clock: impl Clock + 'static) -> Self {
+        self.shared.timers.set_clock(Arc::new(clock));
+        self
+    }
+
+    /// Runs `f` on a worker once `deadline` has passed, as told by the pool's clock.
+    ///
+    /// Like every other job, it still has to wait in the queue if all workers are busy.
+    ///
+    /// # Errors
+    ///
+    /// The timers run on a thread of their own, which is started with the first timer.
+    /// If the operating system can't start it, an error is returned and the job is dropped.
+    pub fn schedule_at<F>(&self, deadline: Instant, f: F) -> io::Result<TimerHandle>
+        where F: FnOnce() + Send + 'static {
+        self.shared.timers.schedule(&self.shared, deadline, Kind::Once(Box::new(f)))
+    }
+
+    /// Runs `f` on a worker after `delay`.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `schedule_at`, if the timer thread can't be started.
+    pub fn schedule_after<F>(&self, delay: Duration, f: F) -> io::Result<TimerHandle>
+        where F: FnOnce() + Send + 'static {
+        self.schedule_at(self.shared.timers.now() + delay, f)
+    }
+
+    /// Runs `f` on a worker every `interval`, starting one interval from now, until the handle is cancelled or the pool is dropped.
+    ///
+    /// If a run is still going when the next one is due, the next one is skipped.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `schedule_at`, if the timer thread can't be started.
+    ///
+    /// # Panics
+    ///
+    /// The `schedule_every` function will panic if the interval is zero.
+    pub fn schedule_every<F>(&self, interval: Duration, f: F) -> io::Result<TimerHandle>
+        where F: Fn() + Send + Sync + 'static {
+        assert!(!interval.is_zero(), "a timer can't repeat every 0 seconds");
+        let kind = Kind::Every {
+            interval,
+            job: Arc::new(f),
+            running: Arc::new(AtomicBool::new(false))
+        };
+        let deadline = self.shared.timers.now() + interval;
+        self.shared.timers.schedule(&self.shared, deadline, kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    const PATIENCE: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn runs_jobs_when_the_clock_says_so() {
+        let clock = ManualClock::new();
+        let pool = ThreadPool::new(2).with_clock(clock.clone());
+        let (done, finished) = mpsc::channel();
+        let later = done.clone();
+        let cancelled = done.clone();
+        pool.schedule_after(Duration::from_secs(60), move || done.send("first").unwrap()).unwrap();
+        pool.schedule_at(clock.now() + Duration::from_secs(120), move || later.send("second").unwrap()).unwrap();
+        pool.schedule_after(Duration::from_secs(30), move || cancelled.send("cancelled").unwrap()).unwrap().cancel();
+
+        clock.advance(Duration::from_secs(90));
+        assert_eq!("first", finished.recv_timeout(PATIENCE).unwrap());
+        assert!(finished.recv_timeout(Duration::from_millis(50)).is_err());
+        clock.advance(Duration::from_secs(30));
+        assert_eq!("second", finished.recv_timeout(PATIENCE).unwrap());
+        drop(pool);
+        assert!(finished.recv().is_err());
+    }
+
+    #[test]
+    fn repeats_until_cancelled() {
+        let clock = ManualClock::new();
+        let pool = ThreadPool::new(1).with_clock(clock.clone());
+        let (ticks, ticked) = mpsc::channel();
+        let ticks = Mutex::new(ticks);
+        let handle = pool.schedule_every(Duration::from_secs(10), move || ticks.lock().unwrap().send(()).unwrap()).unwrap();
+
+        // A tick that comes while the last run is still finishing up would be skipped, so every run is waited for.
+        let tick = |by, runs| {
+            clock.advance(Duration::from_secs(by));
+            ticked.recv_timeout(PATIENCE).unwrap();
+            let waiting_since = Instant::now();
+            while pool.metrics().completed < runs {
+                assert!(waiting_since.elapsed() < PATIENCE, "run {runs} never finished");
+                thread::yield_now();
+            }
+        };
+        for runs in 1..=3 {
+            tick(10, runs);
+        }
+        // Missed runs are skipped instead of being made up for all at once.
+        tick(45, 4);
+        assert!(ticked.recv_timeout(Duration::from_millis(50)).is_err());
+
+        handle.cancel();
+        clock.advance(Duration::from_secs(60));
+        assert!(ticked.recv_timeout(Duration::from_millis(50)).is_err());
+    }
+
+    #[test]
+    fn skips_missed_beats() {
+        let start = Instant::now();
+        let interval = Duration::from_secs(10);
+        assert_eq!(start + interval, next_deadline(start, interval, start + Duration::from_secs(3)));
+        assert_eq!(start + Duration::from_secs(50), next_deadline(start, interval, start + Duration::from_secs(45)));
+        assert_eq!(start + Duration::from_secs(50), next_deadline(start, interval, start + Duration::from_secs(40)));
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{Handler, Histogram, Params, PoolMetrics, PoolMonitor, Request, Response, StatusCode};
+use std::fmt::Write;
+
+const PREFIX: &str = "hello_http_pool";
+
+/// A handler that shows the metrics of a `ThreadPool` in the Prometheus text format, e.g. for `GET /metrics`.
+pub struct MetricsHandler {
+    monitor: PoolMonitor
+}
+
+impl MetricsHandler {
+    pub fn new(monitor: PoolMonitor) -> Self {
+        MetricsHandler { monitor }
+    }
+}
+
+impl Handler for MetricsHandler {
+    fn handle(&self, _request: &Request, _params: &Params) -> Response {
+        Response::new(StatusCode::Ok)
+            .with_header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+            .with_body(render(&self.monitor.metrics()))
+    }
+}
+
+fn render(metrics: &PoolMetrics) -> String {
+    let mut out = String::new();
+    gauge(&mut out, "queued_jobs", "Jobs waiting for a free worker.", metrics.queued);
+    gauge(&mut out, "workers", "Workers that are currently running.", metrics.workers);
+    gauge(&mut out, "busy_workers", "Workers that are running a job.", metrics.busy);
+    gauge(&mut out, "idle_workers", "Workers that are waiting for a job.", metrics.idle);
+    counter(&mut out, "jobs_completed_total", "Jobs that ran to the end.", metrics.completed);
+    counter(&mut out, "jobs_panicked_total", "Jobs that panicked.", metrics.panicked);
+    histogram(&mut out, "job_wait_seconds", "How long jobs waited in the queue.", &metrics.wait_time);
+    histogram(&mut out, "job_run_seconds", "How long jobs took to run.", &metrics.run_time);
+    out
+}
+
+// Writing into a `String` can't fail, so the results of `writeln!` are ignored.
+fn gauge(out: &mut String, name: &str, help: &str, value: usize) {
+    let _ = writeln!(out, "# HELP {PREFIX}_{name} {help}");
+    let _ = writeln!(out, "# TYPE {PREFIX}_{name} gauge");
+    let _ = writeln!(out, "{PREFIX}_{name} {value}");
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {PREFIX}_{name} {help}");
+    let _ = writeln!(out, "# TYPE {PREFIX}_{name} counter");
+    let _ = writeln!(out, "{PREFIX}_{name} {value}");
+}
+
+fn histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    let _ = writeln!(out, "# HELP {PREFIX}_{name} {help}");
+    let _ = writeln!(out, "# TYPE {PREFIX}_{name} histogram");
+    for (bound, count) in &histogram.buckets {
+        let _ = writeln!(out, "{PREFIX}_{name}_bucket{{le=\"{}\"}} {count}", bound.as_secs_f64());
+    }
+    let _ = writeln!(out, "{PREFIX}_{name}_bucket{{le=\"+Inf\"}} {}", histogram.count);
+    let _ = writeln!(out, "{PREFIX}_{name}_sum {}", histogram.sum.as_secs_f64());
+    let _ = writeln!(out, "{PREFIX}_{name}_count {}", histogram.count);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn renders_the_text_format() {
+        let metrics = PoolMetrics {
+            queued: 2,
+            workers: 4,
+            busy: 3,
+            idle: 1,
+            completed: 10,
+            panicked: 1,
+            wait_time: Histogram {
+                buckets: vec![(Duration::from_millis(1), 7), (Duration::from_secs(1), 10)],
+                count: 11,
+                sum: Duration::from_millis(2500)
+            },
+            run_time: Histogram::default()
+        };
+        let text = render(&metrics);
+
+        assert!(text.contains("# TYPE hello_http_pool_queued_jobs gauge\nhello_http_pool_queued_jobs 2\n"));
+        assert!(text.contains("hello_http_pool_jobs_panicked_total 1\n"));
+        assert!(text.contains("hello_http_pool_job_wait_seconds_bucket{le=\"0.001\"} 7\n"));
+        assert!(text.contains("hello_http_pool_job_wait_seconds_bucket{le=\"+Inf\"} 11\n"));
+        assert!(text.contains("hello_http_pool_job_wait_seconds_sum 2.5\n"));
+        assert!(text.contains("hello_http_pool_job_run_seconds_count 0\n"));
+    }
+}
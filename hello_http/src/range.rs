@@ -0,0 +1,81 @@
+use std::ops::Range;
+
+// Every range needs its own part in a multipart response, so a client asking for thousands of tiny ranges could make us do a lot of work for little data.
+const MAX_RANGES: usize = 16;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Ranges {
+    /// The header is missing, malformed or unreasonable, so the whole representation should be sent.
+    Ignore,
+    /// None of the ranges overlaps the representation.
+    Unsatisfiable,
+    Satisfiable(Vec<Range<u64>>)
+}
+
+/// Parses a `Range` header like `bytes=0-99, 200-, -500` for a representation of `length` bytes.
+pub fn parse_ranges(header: &str, length: u64) -> Ranges {
+    let Some(specs) = header.trim().strip_prefix("bytes=") else {
+        return Ranges::Ignore;
+    };
+    let mut ranges = Vec::new();
+    for spec in specs.split(',') {
+        let Some((start, end)) = spec.trim().split_once('-') else {
+            return Ranges::Ignore;
+        };
+        let range = match (start.trim(), end.trim()) {
+            // `-500` means the last 500 bytes.
+            // An empty representation has no last bytes, so the suffix doesn't overlap it, however long it is.
+            ("", suffix) => match suffix.parse::<u64>() {
+                Ok(0) => continue,
+                Ok(_) if length == 0 => continue,
+                Ok(suffix) => length.saturating_sub(suffix)..length,
+                Err(_) => return Ranges::Ignore
+            },
+            (start, end) => {
+                let Ok(start) = start.parse::<u64>() else {
+                    return Ranges::Ignore;
+                };
+                let end = match end {
+                    "" => length,
+                    end => match end.parse::<u64>() {
+                        Ok(end) if end >= start => end.saturating_add(1).min(length),
+                        _ => return Ranges::Ignore
+                    }
+                };
+                if start >= length {
+                    continue;
+                }
+                start..end
+            }
+        };
+        ranges.push(range);
+        if ranges.len() > MAX_RANGES {
+            return Ranges::Ignore;
+        }
+    }
+
+    if ranges.is_empty() {
+        Ranges::Unsatisfiable
+    } else {
+        Ranges::Satisfiable(ranges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    // A single range in a `vec!` is exactly what's meant here, not a typo for the range's elements.
+    #[allow(clippy::single_range_in_vec_init)]
+    fn parses_byte_ranges() {
+        assert_eq!(Ranges::Satisfiable(vec![0..100]), parse_ranges("bytes=0-99", 1000));
+        assert_eq!(Ranges::Satisfiable(vec![500..1000, 900..1000]), parse_ranges("bytes=500-, -100", 1000));
+        assert_eq!(Ranges::Satisfiable(vec![990..1000]), parse_ranges("bytes=990-2000", 1000));
+        assert_eq!(Ranges::Unsatisfiable, parse_ranges("bytes=1000-1001", 1000));
+        assert_eq!(Ranges::Unsatisfiable, parse_ranges("bytes=-5", 0));
+        assert_eq!(Ranges::Unsatisfiable, parse_ranges("bytes=0-, -5", 0));
+        assert_eq!(Ranges::Ignore, parse_ranges("bytes=5-1", 1000));
+        assert_eq!(Ranges::Ignore, parse_ranges("lines=1-2", 1000));
+    }
+}
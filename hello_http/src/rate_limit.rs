@@ -0,0 +1,230 @@
+use crate::{Context, Middleware, Request, Response, StatusCode};
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant}
+};
+
+/// What a `RateLimit` tells clients apart by.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum RateLimitKey {
+    /// The IP address of the connection.
+    #[default]
+    PeerAddr,
+    /// The last value of a header a proxy in front of the server sets, e.g. `X-Forwarded-For` or `X-Real-IP`.
+    /// Clients can send any header themselves, so this is only safe if the proxy always overwrites or appends to it.
+    /// Requests without the header are told apart by their IP address.
+    Header(String)
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant
+}
+
+struct State {
+    buckets: HashMap<String, Bucket>,
+    last_sweep: Instant
+}
+
+enum Decision {
+    Allowed,
+    Limited { retry_after: Duration }
+}
+
+/// A middleware that limits how many requests each client can make, with a token bucket per client.
+///
+/// Every client starts with a full bucket of `capacity` tokens, which refills evenly over `period`.
+/// Each request takes a token, and a client with an empty bucket gets `429 Too Many Requests` with a `Retry-After` header.
+/// All responses carry `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset`, as the IETF draft describes them.
+///
+/// Each instance has its own buckets, so route groups can get different limits with `Router::wrap`.
+pub struct RateLimit {
+    capacity: u32,
+    period: Duration,
+    key: RateLimitKey,
+    state: Mutex<State>
+}
+
+impl RateLimit {
+    /// Allows `capacity` requests per `period`, all at once or spread out.
+    ///
+    /// # Panics
+    ///
+    /// The `new` function will panic if `capacity` or `period` is zero.
+    pub fn new(capacity: u32, period: Duration) -> Self {
+        assert!(capacity > 0, "a rate limit has to allow at least one request");
+        assert!(!period.is_zero(), "a rate limit needs a period that isn't 0 seconds");
+        RateLimit {
+            capacity,
+            period,
+            key: RateLimitKey::default(),
+            state: Mutex::new(State {
+                buckets: HashMap::new(),
+                last_sweep: Instant::now()
+            })
+        }
+    }
+
+    pub fn with_key(mut self, key: RateLimitKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn key_of(&self, request: &Request) -> String {
+        let header = match &self.key {
+            RateLimitKey::PeerAddr => None,
+            // Proxies append the address they saw, so the last entry is the one the client couldn't make up.
+            // That holds across field lines too: a proxy may add its own line after the ones the client sent.
+            RateLimitKey::Header(name) => request
+                .headers()
+                .get_all(name)
+                .last()
+                .and_then(|value| value.rsplit(',').next())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+        match (header, request.peer_addr()) {
+            (Some(value), _) => value.to_string(),
+            (None, Some(address)) => address.ip().to_string(),
+            (None, None) => String::from("unknown")
+        }
+    }
+
+    fn rate(&self) -> f64 {
+        f64::from(self.capacity) / self.period.as_secs_f64()
+    }
+
+    // Fills the bucket up for the time that passed since it was last used.
+    fn refill(&self, bucket: &mut Bucket, now: Instant) {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate()).min(f64::from(self.capacity));
+        bucket.updated = now;
+    }
+
+    fn check(&self, key: String, now: Instant) -> Decision {
+        let mut state = self.lock();
+        // A bucket that has been left alone long enough to be full again is no different from a new one, so it can go.
+        // Sweeping once per period keeps the map from growing with every client that ever came by.
+        if now.saturating_duration_since(state.last_sweep) >= self.period {
+            state.buckets.retain(|_, bucket| now.saturating_duration_since(bucket.updated) < self.period);
+            state.last_sweep = now;
+        }
+        let bucket = state.buckets
+            .entry(key)
+            .or_insert(Bucket { tokens: f64::from(self.capacity), updated: now });
+        self.refill(bucket, now);
+        if bucket.tokens < 1.0 {
+            let retry_after = Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate());
+            return Decision::Limited { retry_after };
+        }
+        bucket.tokens -= 1.0;
+        Decision::Allowed
+    }
+
+    fn with_headers(&self, response: Response, remaining: u32, reset: Duration) -> Response {
+        response
+            .with_header("RateLimit-Limit", self.capacity.to_string())
+            .with_header("RateLimit-Remaining", remaining.to_string())
+            .with_header("RateLimit-Reset", whole_seconds(reset).to_string())
+            .with_header("RateLimit-Policy", format!("{};w={}", self.capacity, whole_seconds(self.period)))
+    }
+}
+
+impl Middleware for RateLimit {
+    fn before(&self, request: &Request, _context: &mut Context) -> Option<Response> {
+        match self.check(self.key_of(request), Instant::now()) {
+            Decision::Allowed => None,
+            Decision::Limited { retry_after } => {
+                let response = Response::text(StatusCode::TooManyRequests, "Too Many Requests\n")
+                    .with_header("Retry-After", whole_seconds(retry_after).to_string());
+                Some(self.with_headers(response, 0, retry_after))
+            }
+        }
+    }
+
+    fn after(&self, request: &Request, response: Response, _context: &mut Context) -> Response {
+        if response.status() == StatusCode::TooManyRequests {
+            return response;
+        }
+        // The request already took its token, so this only looks at the bucket without taking another one.
+        let now = Instant::now();
+        let mut state = self.lock();
+        let Some(bucket) = state.buckets.get_mut(&self.key_of(request)) else {
+            return response;
+        };
+        self.refill(bucket, now);
+        let bucket = *bucket;
+        drop(state);
+        let reset = Duration::from_secs_f64((f64::from(self.capacity) - bucket.tokens) / self.rate());
+        self.with_headers(response, bucket.tokens as u32, reset)
+    }
+}
+
+// Clients are told to wait at least a second, because waiting for less than they're told is what gets them limited again.
+fn whole_seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Handler, Params, Router};
+
+    #[test]
+    fn refills_the_bucket_over_time() {
+        let limit = RateLimit::new(2, Duration::from_secs(10));
+        let start = Instant::now();
+        let key = || String::from("192.0.2.1");
+
+        assert!(matches!(limit.check(key(), start), Decision::Allowed));
+        assert!(matches!(limit.check(key(), start), Decision::Allowed));
+        let Decision::Limited { retry_after } = limit.check(key(), start + Duration::from_secs(1)) else {
+            panic!("the bucket should be empty");
+        };
+        assert_eq!(Duration::from_secs(4), retry_after);
+        // Someone else has their own bucket.
+        assert!(matches!(limit.check(String::from("192.0.2.2"), start), Decision::Allowed));
+        assert!(matches!(limit.check(key(), start + Duration::from_secs(5)), Decision::Allowed));
+        assert!(matches!(limit.check(key(), start + Duration::from_secs(5)), Decision::Limited { .. }));
+
+        // Once a period went by, buckets that are full again are thrown away.
+        limit.check(key(), start + Duration::from_secs(12));
+        assert_eq!(1, limit.lock().buckets.len());
+    }
+
+    #[test]
+    fn answers_with_429_and_rate_limit_headers() {
+        let mut router = Router::new();
+        router
+            .get("/sleep", |_: &Request, _: &Params| Response::text(StatusCode::Ok, "slept"))
+            .wrap("/sleep", RateLimit::new(1, Duration::from_secs(60)).with_key(RateLimitKey::Header(String::from("X-Forwarded-For"))));
+        let get = |client: &str| {
+            let raw = format!("GET /sleep HTTP/1.1\r\nX-Forwarded-For: 10.0.0.1, {client}\r\n\r\n");
+            router.handle(&Request::parse(&mut raw.as_bytes()).unwrap(), &Params::default())
+        };
+
+        let response = get("203.0.113.9");
+        assert_eq!(StatusCode::Ok, response.status());
+        assert_eq!(Some("1"), response.headers().get("ratelimit-limit"));
+        assert_eq!(Some("0"), response.headers().get("ratelimit-remaining"));
+        assert_eq!(Some("1;w=60"), response.headers().get("ratelimit-policy"));
+
+        let response = get("203.0.113.9");
+        assert_eq!(StatusCode::TooManyRequests, response.status());
+        assert_eq!(Some("60"), response.headers().get("retry-after"));
+        assert_eq!(StatusCode::Ok, get("203.0.113.10").status());
+
+        // A forged first line doesn't help, when the proxy's line comes after it.
+        let raw = "GET /sleep HTTP/1.1\r\nX-Forwarded-For: 198.51.100.1\r\nX-Forwarded-For: 203.0.113.9\r\n\r\n";
+        let response = router.handle(&Request::parse(&mut raw.as_bytes()).unwrap(), &Params::default());
+        assert_eq!(StatusCode::TooManyRequests, response.status());
+    }
+}
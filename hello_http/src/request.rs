@@ -0,0 +1,554 @@
+use crate::{chunked::read_chunked, Headers, StatusCode};
+use std::{
+    error::Error,
+    fmt,
+    io::{self, prelude::*},
+    net::SocketAddr,
+    str::FromStr
+};
+
+// Without a limit, a client could send a single endless line and we would keep growing the buffer.
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Connect => "CONNECT",
+            Method::Options => "OPTIONS",
+            Method::Trace => "TRACE",
+            Method::Patch => "PATCH"
+        }
+    }
+}
+
+impl FromStr for Method {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Methods are case-sensitive, so `get` is not the same as `GET`.
+        match s {
+            "GET" => Ok(Method::Get),
+            "HEAD" => Ok(Method::Head),
+            "POST" => Ok(Method::Post),
+            "PUT" => Ok(Method::Put),
+            "DELETE" => Ok(Method::Delete),
+            "CONNECT" => Ok(Method::Connect),
+            "OPTIONS" => Ok(Method::Options),
+            "TRACE" => Ok(Method::Trace),
+            "PATCH" => Ok(Method::Patch),
+            _ => Err(ParseError::UnknownMethod(s.to_string()))
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Version {
+    Http10,
+    Http11
+}
+
+impl Version {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1"
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Upper bounds for what a client may send.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// The largest body that is accepted, no matter if it's sent with `Content-Length` or chunked.
+    pub max_body_size: u64,
+    /// How many header fields a request may have.
+    pub max_headers: usize,
+    /// How many bytes the request line and all header fields may take together.
+    pub max_header_size: usize
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_body_size: 1024 * 1024,
+            max_headers: 100,
+            max_header_size: 64 * 1024
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    Io(io::Error),
+    /// The client closed the connection before sending anything.
+    ConnectionClosed,
+    /// The connection ended in the middle of a request.
+    UnexpectedEof,
+    LineTooLong,
+    MalformedRequestLine,
+    UnknownMethod(String),
+    UnsupportedVersion(String),
+    InvalidTarget,
+    MalformedHeader,
+    InvalidContentLength,
+    InvalidEncoding,
+    InvalidChunk,
+    UnsupportedTransferEncoding,
+    PayloadTooLarge,
+    TooManyHeaders,
+    HeadersTooLarge,
+    /// The client took longer than allowed to send the request.
+    TimedOut
+}
+
+impl ParseError {
+    /// The status code of the response that tells the client what was wrong.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ParseError::UnsupportedVersion(_) => StatusCode::HttpVersionNotSupported,
+            ParseError::UnsupportedTransferEncoding => StatusCode::NotImplemented,
+            ParseError::PayloadTooLarge => StatusCode::PayloadTooLarge,
+            ParseError::TooManyHeaders | ParseError::HeadersTooLarge => StatusCode::RequestHeaderFieldsTooLarge,
+            ParseError::TimedOut => StatusCode::RequestTimeout,
+            _ => StatusCode::BadRequest
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Io(error) => write!(f, "failed to read request: {error}"),
+            ParseError::ConnectionClosed => write!(f, "connection closed before a request was sent"),
+            ParseError::UnexpectedEof => write!(f, "connection closed in the middle of a request"),
+            ParseError::LineTooLong => write!(f, "line exceeds {MAX_LINE_LENGTH} bytes"),
+            ParseError::MalformedRequestLine => write!(f, "malformed request line"),
+            ParseError::UnknownMethod(method) => write!(f, "unknown method {method:?}"),
+            ParseError::UnsupportedVersion(version) => write!(f, "unsupported version {version:?}"),
+            ParseError::InvalidTarget => write!(f, "invalid request target"),
+            ParseError::MalformedHeader => write!(f, "malformed header field"),
+            ParseError::InvalidContentLength => write!(f, "invalid Content-Length"),
+            ParseError::InvalidEncoding => write!(f, "invalid percent-encoding"),
+            ParseError::InvalidChunk => write!(f, "malformed chunk in chunked body"),
+            ParseError::UnsupportedTransferEncoding => write!(f, "unsupported Transfer-Encoding"),
+            ParseError::PayloadTooLarge => write!(f, "request body is too large"),
+            ParseError::TooManyHeaders => write!(f, "too many header fields"),
+            ParseError::HeadersTooLarge => write!(f, "header fields are too large"),
+            ParseError::TimedOut => write!(f, "request took too long to arrive")
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Io(error) => Some(error),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(error: io::Error) -> Self {
+        // A read timeout shows up as `WouldBlock` on some platforms and as `TimedOut` on others.
+        match error.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ParseError::TimedOut,
+            _ => ParseError::Io(error)
+        }
+    }
+}
+
+/// A parsed HTTP/1.x request.
+///
+/// The path is kept exactly as the client sent it (still percent-encoded), so that an encoded `/` can't be confused with a real one.
+/// Query parameters are decoded.
+#[derive(Clone, Debug)]
+pub struct Request {
+    method: Method,
+    target: String,
+    path: String,
+    query: Vec<(String, String)>,
+    version: Version,
+    headers: Headers,
+    body: Vec<u8>,
+    trailers: Headers,
+    peer_addr: Option<SocketAddr>
+}
+
+impl Request {
+    /// Reads one request from `reader`.
+    ///
+    /// Only the bytes that belong to this request are consumed, so the same reader can be used for the next request on the connection.
+    pub fn parse<R: BufRead>(reader: &mut R) -> Result<Request, ParseError> {
+        Self::parse_with_limits(reader, &Limits::default())
+    }
+
+    /// Reads one request from `reader`, rejecting it if it exceeds `limits`.
+    ///
+    /// Bodies sent with `Transfer-Encoding: chunked` are decoded, so the handler doesn't see a difference.
+    pub fn parse_with_limits<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Request, ParseError> {
+        let mut request = Self::parse_head(reader, limits)?;
+        request.read_body(reader, limits)?;
+        Ok(request)
+    }
+
+    /// Reads the request line and the header fields, but not the body.
+    /// The connection uses this to give the head and the body their own timeouts.
+    pub(crate) fn parse_head<R: BufRead>(reader: &mut R, limits: &Limits) -> Result<Request, ParseError> {
+        let mut request_line = read_line(reader)?.ok_or(ParseError::ConnectionClosed)?;
+        // Clients may send empty lines before the request line, which should be ignored.
+        while request_line.is_empty() {
+            request_line = read_line(reader)?.ok_or(ParseError::ConnectionClosed)?;
+        }
+
+        let mut parts = request_line.split(' ');
+        let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version), None) => (method, target, version),
+            _ => return Err(ParseError::MalformedRequestLine)
+        };
+        let method: Method = method.parse()?;
+        let version = match version {
+            "HTTP/1.1" => Version::Http11,
+            "HTTP/1.0" => Version::Http10,
+            _ => return Err(ParseError::UnsupportedVersion(version.to_string()))
+        };
+        let (path, query) = parse_target(method, target)?;
+
+        let mut headers = Headers::new();
+        let mut head_size = request_line.len();
+        loop {
+            let line = read_line(reader)?.ok_or(ParseError::UnexpectedEof)?;
+            if line.is_empty() {
+                break;
+            }
+            // Every single line is short enough, but a client could still send an endless number of them.
+            head_size += line.len();
+            if head_size > limits.max_header_size {
+                return Err(ParseError::HeadersTooLarge);
+            }
+            if headers.len() >= limits.max_headers {
+                return Err(ParseError::TooManyHeaders);
+            }
+            let (name, value) = parse_header(&line)?;
+            headers.append(name, value);
+        }
+
+        Ok(Request {
+            method,
+            target: target.to_string(),
+            path,
+            query,
+            version,
+            headers,
+            body: Vec::new(),
+            trailers: Headers::new(),
+            peer_addr: None
+        })
+    }
+
+    /// Reads the body that the header fields announced.
+    pub(crate) fn read_body<R: BufRead>(&mut self, reader: &mut R, limits: &Limits) -> Result<(), ParseError> {
+        let headers = &self.headers;
+        if headers.contains("Transfer-Encoding") {
+            // A body with both a length and a transfer coding is a classic way to smuggle a second request past a proxy.
+            if self.version == Version::Http10 || headers.contains("Content-Length") {
+                return Err(ParseError::InvalidContentLength);
+            }
+            // Chunked has to be the only (and last) coding, because other codings like gzip aren't supported.
+            let codings: Vec<&str> = headers
+                .get_all("Transfer-Encoding")
+                .flat_map(|value| value.split(','))
+                .map(str::trim)
+                .collect();
+            if !codings.iter().all(|coding| coding.eq_ignore_ascii_case("chunked")) || codings.len() != 1 {
+                return Err(ParseError::UnsupportedTransferEncoding);
+            }
+            (self.body, self.trailers) = read_chunked(reader, limits)?;
+        }
+        let content_length = content_length(&self.headers)?;
+        if content_length > limits.max_body_size {
+            return Err(ParseError::PayloadTooLarge);
+        }
+        if content_length > 0 {
+            reader
+                .take(content_length)
+                .read_to_end(&mut self.body)?;
+            if (self.body.len() as u64) < content_length {
+                return Err(ParseError::UnexpectedEof);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    /// The request target as it appeared in the request line, including the query string.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// The path of the request target without the query string.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the decoded value of the first query parameter called `name`.
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// All decoded query parameters in the order they appeared.
+    pub fn query_pairs(&self) -> &[(String, String)] {
+        &self.query
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// The fields sent after a chunked body.
+    pub fn trailers(&self) -> &Headers {
+        &self.trailers
+    }
+
+    /// The address of the client, if the request came in over a network connection.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    pub(crate) fn set_peer_addr(&mut self, peer_addr: Option<SocketAddr>) {
+        self.peer_addr = peer_addr;
+    }
+}
+
+/// Reads a line without its line ending.
+/// Returns `None` if the reader was already at the end.
+pub(crate) fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<String>, ParseError> {
+    let mut line = Vec::new();
+    // `take` makes sure `read_until` stops, even if the newline never arrives.
+    let read = reader
+        .take(MAX_LINE_LENGTH as u64 + 1)
+        .read_until(b'\n', &mut line)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.last() != Some(&b'\n') {
+        return if line.len() > MAX_LINE_LENGTH {
+            Err(ParseError::LineTooLong)
+        } else {
+            Err(ParseError::UnexpectedEof)
+        };
+    }
+    line.pop();
+    // The standard asks for CRLF, but recommends accepting a bare LF too.
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .map_err(|_| ParseError::MalformedHeader)
+}
+
+fn parse_target(method: Method, target: &str) -> Result<(String, Vec<(String, String)>), ParseError> {
+    if target == "*" && method == Method::Options {
+        return Ok((target.to_string(), Vec::new()));
+    }
+    if !target.starts_with('/') {
+        return Err(ParseError::InvalidTarget);
+    }
+    // A fragment is never sent by a well-behaved client, so it is simply ignored.
+    let target = target
+        .split('#')
+        .next()
+        .unwrap_or_default();
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, parse_query(query)?),
+        None => (target, Vec::new())
+    };
+    Ok((path.to_string(), query))
+}
+
+fn parse_query(query: &str) -> Result<Vec<(String, String)>, ParseError> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            // In query strings (and only there), `+` stands for a space.
+            let name = percent_decode(&name.replace('+', " "))?;
+            let value = percent_decode(&value.replace('+', " "))?;
+            Ok((name, value))
+        })
+        .collect()
+}
+
+pub(crate) fn parse_header(line: &str) -> Result<(&str, &str), ParseError> {
+    let (name, value) = line
+        .split_once(':')
+        .ok_or(ParseError::MalformedHeader)?;
+    // Whitespace between the name and the colon, as well as lines folded into the previous one, must be rejected.
+    let is_token = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    if name.is_empty() || !name.chars().all(is_token) {
+        return Err(ParseError::MalformedHeader);
+    }
+    Ok((name, value.trim_matches(|c| c == ' ' || c == '\t')))
+}
+
+fn content_length(headers: &Headers) -> Result<u64, ParseError> {
+    let mut length = None;
+    for value in headers.get_all("Content-Length") {
+        let value: u64 = value
+            .trim()
+            .parse()
+            .map_err(|_| ParseError::InvalidContentLength)?;
+        // Repeated fields are only fine if they agree, otherwise we can't know where the body ends.
+        if length.is_some_and(|length| length != value) {
+            return Err(ParseError::InvalidContentLength);
+        }
+        length = Some(value);
+    }
+    Ok(length.unwrap_or(0))
+}
+
+/// Decodes `%XX` sequences.
+/// The result must be valid UTF-8.
+pub(crate) fn percent_decode(input: &str) -> Result<String, ParseError> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .ok_or(ParseError::InvalidEncoding)?;
+            let byte = u8::from_str_radix(hex, 16).map_err(|_| ParseError::InvalidEncoding)?;
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| ParseError::InvalidEncoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<Request, ParseError> {
+        Request::parse(&mut raw.as_bytes())
+    }
+
+    #[test]
+    fn parses_request_line_and_query() {
+        let request = parse("GET /search?q=rust+book&page=2&name=J%C3%BCrg HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        assert_eq!(Method::Get, request.method());
+        assert_eq!(Version::Http11, request.version());
+        assert_eq!("/search", request.path());
+        assert_eq!(Some("rust book"), request.query("q"));
+        assert_eq!(Some("2"), request.query("page"));
+        assert_eq!(Some("Jürg"), request.query("name"));
+        assert_eq!(Some("localhost"), request.header("host"));
+    }
+
+    #[test]
+    fn reads_body_by_content_length() {
+        let mut raw = "POST /posts HTTP/1.1\r\ncontent-length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n\r\n".as_bytes();
+
+        let first = Request::parse(&mut raw).unwrap();
+        let second = Request::parse(&mut raw).unwrap();
+
+        assert_eq!(b"hello", first.body());
+        assert_eq!("/", second.path());
+        assert!(matches!(Request::parse(&mut raw), Err(ParseError::ConnectionClosed)));
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        assert!(matches!(parse("GET /\r\n\r\n"), Err(ParseError::MalformedRequestLine)));
+        assert!(matches!(parse("FETCH / HTTP/1.1\r\n\r\n"), Err(ParseError::UnknownMethod(_))));
+        assert!(matches!(parse("GET / HTTP/2.0\r\n\r\n"), Err(ParseError::UnsupportedVersion(_))));
+        assert!(matches!(parse("GET / HTTP/1.1\r\nHost : x\r\n\r\n"), Err(ParseError::MalformedHeader)));
+        assert!(matches!(parse("GET /?q=%zz HTTP/1.1\r\n\r\n"), Err(ParseError::InvalidEncoding)));
+        assert!(matches!(parse("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort"), Err(ParseError::UnexpectedEof)));
+        assert!(matches!(parse("POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nab"), Err(ParseError::InvalidContentLength)));
+    }
+
+    #[test]
+    fn decodes_chunked_bodies() {
+        let mut raw = "POST /upload HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki\r\n5\r\npedia\r\n0\r\nDigest: x\r\n\r\n".as_bytes();
+        let request = Request::parse(&mut raw).unwrap();
+
+        assert_eq!(b"Wikipedia", request.body());
+        assert_eq!(Some("x"), request.trailers().get("digest"));
+
+        let limits = Limits { max_body_size: 4, ..Limits::default() };
+        let mut raw = "POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello".as_bytes();
+        assert!(matches!(Request::parse_with_limits(&mut raw, &limits), Err(ParseError::PayloadTooLarge)));
+        assert!(matches!(parse("POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n"), Err(ParseError::UnsupportedTransferEncoding)));
+        assert!(matches!(parse("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n"), Err(ParseError::InvalidContentLength)));
+    }
+
+    #[test]
+    fn limits_the_header_fields() {
+        let limits = Limits { max_headers: 2, max_header_size: 64, ..Limits::default() };
+        let mut raw = "GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n".as_bytes();
+        assert!(matches!(Request::parse_with_limits(&mut raw, &limits), Err(ParseError::TooManyHeaders)));
+
+        let long = "x".repeat(60);
+        let raw = format!("GET / HTTP/1.1\r\nCookie: {long}\r\n\r\n");
+        let error = Request::parse_with_limits(&mut raw.as_bytes(), &limits).unwrap_err();
+        assert!(matches!(error, ParseError::HeadersTooLarge));
+        assert_eq!(StatusCode::RequestHeaderFieldsTooLarge, error.status());
+    }
+}
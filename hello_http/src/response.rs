@@ -0,0 +1,353 @@
+use crate::{connection::Upgraded, date::format_http_date, ChunkedWriter, Headers, StatusCode};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, prelude::*},
+    time::SystemTime
+};
+
+/// The payload of a response.
+#[derive(Default)]
+pub enum Body {
+    #[default]
+    Empty,
+    Bytes(Vec<u8>),
+    Text(String),
+    /// A file that is copied to the client piece by piece instead of being loaded into memory first.
+    File {
+        file: File,
+        length: u64
+    },
+    /// Any other source whose length is known up front.
+    Reader {
+        reader: Box<dyn Read + Send>,
+        length: u64
+    },
+    /// A body that is produced while it's being sent, so its length isn't known up front.
+    /// It's sent with `Transfer-Encoding: chunked`.
+    Stream(Box<StreamFn>)
+}
+
+/// The function behind `Body::Stream`, which writes the body piece by piece.
+pub type StreamFn = dyn FnOnce(&mut ChunkedWriter<'_>) -> io::Result<()> + Send;
+
+impl Body {
+    /// Opens a file body, taking the length from the file's metadata.
+    pub fn file(file: File) -> io::Result<Body> {
+        let length = file.metadata()?.len();
+        Ok(Body::File { file, length })
+    }
+
+    /// Creates a streamed body, which is written by `f` once the response is sent.
+    pub fn stream<F>(f: F) -> Body
+        where F: FnOnce(&mut ChunkedWriter<'_>) -> io::Result<()> + Send + 'static {
+        Body::Stream(Box::new(f))
+    }
+
+    /// The length of the body, or `None` for a streamed body.
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Empty => Some(0),
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::Text(text) => Some(text.len() as u64),
+            Body::File { length, .. } | Body::Reader { length, .. } => Some(*length),
+            Body::Stream(_) => None
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// Returns the body, if it is already in memory.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Empty => Some(&[]),
+            Body::Bytes(bytes) => Some(bytes),
+            Body::Text(text) => Some(text.as_bytes()),
+            Body::File { .. } | Body::Reader { .. } | Body::Stream(_) => None
+        }
+    }
+
+    fn write_to<W: Write>(self, writer: &mut W, stream_limit: u64) -> io::Result<()> {
+        match self {
+            Body::Empty => Ok(()),
+            Body::Bytes(bytes) => writer.write_all(&bytes),
+            Body::Text(text) => writer.write_all(text.as_bytes()),
+            Body::File { file, length } => copy_exactly(file, length, writer),
+            Body::Reader { reader, length } => copy_exactly(reader, length, writer),
+            Body::Stream(f) => {
+                let mut chunked = ChunkedWriter::new(writer, stream_limit);
+                f(&mut chunked)?;
+                chunked.finish()
+            }
+        }
+    }
+}
+
+fn copy_exactly<R: Read, W: Write>(reader: R, length: u64, writer: &mut W) -> io::Result<()> {
+    let copied = io::copy(&mut reader.take(length), writer)?;
+    // The length was already promised in `Content-Length`, so a source that shrank in the meantime can't be answered correctly anymore.
+    if copied < length {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "body shrank while it was sent"));
+    }
+    Ok(())
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Empty => write!(f, "Empty"),
+            Body::Bytes(bytes) => f.debug_tuple("Bytes").field(bytes).finish(),
+            Body::Text(text) => f.debug_tuple("Text").field(text).finish(),
+            Body::File { file, length } => f.debug_struct("File").field("file", file).field("length", length).finish(),
+            Body::Reader { length, .. } => f.debug_struct("Reader").field("length", length).finish_non_exhaustive(),
+            Body::Stream(_) => f.debug_tuple("Stream").finish_non_exhaustive()
+        }
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Body::Text(text)
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Self {
+        Body::Text(text.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<&[u8]> for Body {
+    fn from(bytes: &[u8]) -> Self {
+        Body::Bytes(bytes.to_vec())
+    }
+}
+
+// Takes over the connection once a `101 Switching Protocols` response was sent, e.g. to speak WebSocket on it.
+pub(crate) struct Upgrade(pub(crate) Box<dyn FnOnce(Upgraded) + Send>);
+
+impl fmt::Debug for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Upgrade").finish_non_exhaustive()
+    }
+}
+
+/// A response that can be written to a client.
+///
+/// The methods starting with `with_` take and return the response, so they can be chained like a builder:
+///
+/// ```
+/// use hello_http::{Response, StatusCode};
+///
+/// let response = Response::new(StatusCode::Ok)
+///     .with_header("Content-Type", "text/plain")
+///     .with_body("Hello!");
+/// ```
+#[derive(Debug)]
+pub struct Response {
+    status: StatusCode,
+    headers: Headers,
+    body: Body,
+    upgrade: Option<Upgrade>
+}
+
+impl Response {
+    /// Creates a response without headers and without a body.
+    pub fn new(status: StatusCode) -> Self {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Body::Empty,
+            upgrade: None
+        }
+    }
+
+    /// Creates a `text/plain` response.
+    pub fn text(status: StatusCode, text: impl Into<String>) -> Self {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(text.into())
+    }
+
+    /// Creates a response whose body is written by `f` while it is sent, e.g. to send a large export without putting it together in memory first.
+    ///
+    /// ```
+    /// use hello_http::{Response, StatusCode};
+    /// use std::io::Write;
+    ///
+    /// let response = Response::stream(StatusCode::Ok, |writer| {
+    ///     for i in 0..3 {
+    ///         writeln!(writer, "line {i}")?;
+    ///     }
+    ///     Ok(())
+    /// });
+    /// ```
+    pub fn stream<F>(status: StatusCode, f: F) -> Self
+        where F: FnOnce(&mut ChunkedWriter<'_>) -> io::Result<()> + Send + 'static {
+        Response::new(status).with_body(Body::stream(f))
+    }
+
+    /// Creates a `text/html` response.
+    pub fn html(status: StatusCode, html: impl Into<String>) -> Self {
+        Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(html.into())
+    }
+
+    pub fn with_status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Sets a header, replacing any previous value.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Body>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    pub fn body(&self) -> &Body {
+        &self.body
+    }
+
+    pub fn body_mut(&mut self) -> &mut Body {
+        &mut self.body
+    }
+
+    pub fn into_body(self) -> Body {
+        self.body
+    }
+
+    /// Hands the connection to `f` after this response was sent, instead of reading the next request from it.
+    pub(crate) fn with_upgrade<F>(mut self, f: F) -> Self
+        where F: FnOnce(Upgraded) + Send + 'static {
+        self.upgrade = Some(Upgrade(Box::new(f)));
+        self
+    }
+
+    pub(crate) fn take_upgrade(&mut self) -> Option<Upgrade> {
+        self.upgrade.take()
+    }
+
+    /// Turns a streamed body into one that is kept in memory.
+    ///
+    /// HTTP/1.0 clients don't understand chunked bodies, so they can only get a stream this way.
+    pub fn buffer_stream(mut self, limit: u64) -> io::Result<Self> {
+        if let Body::Stream(f) = self.body {
+            let mut body = Vec::new();
+            let mut writer = ChunkedWriter::unencoded(&mut body, limit);
+            f(&mut writer)?;
+            writer.finish()?;
+            self.body = Body::Bytes(body);
+        }
+        Ok(self)
+    }
+
+    /// Writes the status line, the headers and (unless `head_only` is set) the body.
+    ///
+    /// `Content-Length` is always derived from the body, and `Date` is added unless the handler already set one.
+    /// Streamed bodies are sent with `Transfer-Encoding: chunked` instead.
+    pub fn write_to<W: Write>(self, writer: &mut W, head_only: bool) -> io::Result<()> {
+        self.write_with_limit(writer, head_only, u64::MAX)
+    }
+
+    /// Like `write_to`, but fails once a streamed body grows past `stream_limit` bytes.
+    pub fn write_with_limit<W: Write>(mut self, writer: &mut W, head_only: bool, stream_limit: u64) -> io::Result<()> {
+        if !self.headers.contains("Date") {
+            self.headers.insert("Date", format_http_date(SystemTime::now()));
+        }
+        self.headers.remove("Content-Length");
+        self.headers.remove("Transfer-Encoding");
+        if self.status.allows_body() {
+            match self.body.len() {
+                Some(length) => self.headers.insert("Content-Length", length.to_string()),
+                None => self.headers.insert("Transfer-Encoding", "chunked")
+            }
+        }
+
+        // Small writes go straight to the socket, so the head is put together in memory first.
+        let head = format!("HTTP/1.1 {}\r\n{}\r\n", self.status, self.headers);
+        writer.write_all(head.as_bytes())?;
+        if !head_only && self.status.allows_body() {
+            self.body.write_to(writer, stream_limit)?;
+        }
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serialize(response: Response, head_only: bool) -> Vec<u8> {
+        let mut output = Vec::new();
+        response.write_to(&mut output, head_only).unwrap();
+        output
+    }
+
+    #[test]
+    fn sets_content_length_and_date() {
+        let output = serialize(Response::new(StatusCode::Ok).with_body(vec![0, 159, 146, 150]), false);
+        let head_end = output.windows(4).position(|window| window == b"\r\n\r\n").unwrap() + 4;
+        let head = String::from_utf8_lossy(&output[..head_end]);
+
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("\r\nContent-Length: 4\r\n"));
+        assert!(head.contains("\r\nDate: "));
+        assert_eq!(&[0, 159, 146, 150], &output[head_end..]);
+    }
+
+    #[test]
+    fn leaves_out_body_when_not_allowed() {
+        let head_output = serialize(Response::text(StatusCode::Ok, "hello"), true);
+        let not_modified = serialize(Response::text(StatusCode::NotModified, "hello"), false);
+
+        assert!(String::from_utf8(head_output).unwrap().ends_with("Content-Length: 5\r\n\r\n"));
+        let not_modified = String::from_utf8(not_modified).unwrap();
+        assert!(not_modified.ends_with("\r\n\r\n"));
+        assert!(!not_modified.contains("Content-Length"));
+    }
+
+    #[test]
+    fn streams_with_chunked_encoding() {
+        let response = Response::stream(StatusCode::Ok, |writer| {
+            writer.write_all(b"Hello")?;
+            writer.flush()?;
+            writer.write_all(b"!")
+        });
+        let output = String::from_utf8(serialize(response, false)).unwrap();
+
+        assert!(output.contains("\r\nTransfer-Encoding: chunked\r\n"));
+        assert!(!output.contains("Content-Length"));
+        assert!(output.ends_with("\r\n\r\n5\r\nHello\r\n1\r\n!\r\n0\r\n\r\n"));
+
+        let buffered = Response::stream(StatusCode::Ok, |writer| writer.write_all(b"Hello"))
+            .buffer_stream(100)
+            .unwrap();
+        assert_eq!(Some(&b"Hello"[..]), buffered.body().as_bytes());
+    }
+}
@@ -0,0 +1,337 @@
+use crate::{middleware, request::percent_decode, Method, Middleware, Request, Response, StatusCode};
+
+/// Anything that can answer a request.
+///
+/// Closures with the right signature are handlers too, so most routes can be registered without a new type.
+pub trait Handler: Send + Sync {
+    fn handle(&self, request: &Request, params: &Params) -> Response;
+}
+
+impl<F> Handler for F
+    where F: Fn(&Request, &Params) -> Response + Send + Sync {
+    fn handle(&self, request: &Request, params: &Params) -> Response {
+        self(request, params)
+    }
+}
+
+/// The values captured by `:name` and `*name` segments of a route pattern.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Params {
+    values: Vec<(String, String)>
+}
+
+impl Params {
+    /// Returns the decoded value of the parameter called `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+#[derive(Debug)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    // Matches all remaining segments, including none at all.
+    Rest(String)
+}
+
+#[derive(Debug)]
+struct Pattern {
+    segments: Vec<Segment>
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Self {
+        assert!(pattern.starts_with('/'), "route pattern {pattern:?} must start with '/'");
+        let parts: Vec<&str> = split_path(pattern).collect();
+        let mut segments = Vec::with_capacity(parts.len());
+        for (i, part) in parts.iter().enumerate() {
+            let segment = if let Some(name) = part.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = part.strip_prefix('*') {
+                assert!(i == parts.len() - 1, "'*{name}' must be the last segment of {pattern:?}");
+                Segment::Rest(name.to_string())
+            } else {
+                Segment::Literal(part.to_string())
+            };
+            segments.push(segment);
+        }
+        Pattern { segments }
+    }
+
+    fn matches(&self, path: &str) -> Option<Params> {
+        let mut parts = split_path(path);
+        let mut values = Vec::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => {
+                    if parts.next()? != literal {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    let part = parts.next().filter(|part| !part.is_empty())?;
+                    values.push((name.clone(), percent_decode(part).ok()?));
+                }
+                Segment::Rest(name) => {
+                    let rest = parts.collect::<Vec<_>>().join("/");
+                    values.push((name.clone(), percent_decode(&rest).ok()?));
+                    return Some(Params { values });
+                }
+            }
+        }
+        match parts.next() {
+            Some(_) => None,
+            None => Some(Params { values })
+        }
+    }
+}
+
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    let path = path.strip_prefix('/').unwrap_or(path);
+    // Splitting an empty string would produce one empty segment, but `/` has no segments at all.
+    path.split('/').filter(move |_| !path.is_empty())
+}
+
+struct Route {
+    method: Method,
+    pattern: Pattern,
+    handler: Box<dyn Handler>
+}
+
+/// Dispatches requests to the handler registered for their method and path.
+///
+/// Patterns are made of literal segments, `:name` for exactly one segment and `*name` for the rest of the path.
+/// Routes are tried in the order they were added.
+/// `HEAD` requests fall back to the `GET` handler of a route.
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Box<dyn Handler>,
+    // The prefixes are kept as normalized segments, see `normalize`.
+    middleware: Vec<(Vec<String>, Box<dyn Middleware>)>
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router {
+            routes: Vec::new(),
+            not_found: Box::new(|_: &Request, _: &Params| Response::text(StatusCode::NotFound, "Not Found\n")),
+            middleware: Vec::new()
+        }
+    }
+
+    /// Registers `handler` for requests with `method` whose path matches `pattern`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic, when `pattern` doesn't start with `/` or has a `*name` segment that isn't the last one.
+    pub fn route<H>(&mut self, method: Method, pattern: &str, handler: H) -> &mut Self
+        where H: Handler + 'static {
+        self.routes.push(Route {
+            method,
+            pattern: Pattern::parse(pattern),
+            handler: Box::new(handler)
+        });
+        self
+    }
+
+    pub fn get<H>(&mut self, pattern: &str, handler: H) -> &mut Self
+        where H: Handler + 'static {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post<H>(&mut self, pattern: &str, handler: H) -> &mut Self
+        where H: Handler + 'static {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    pub fn put<H>(&mut self, pattern: &str, handler: H) -> &mut Self
+        where H: Handler + 'static {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    pub fn delete<H>(&mut self, pattern: &str, handler: H) -> &mut Self
+        where H: Handler + 'static {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    /// Replaces the handler that answers requests no route matches.
+    pub fn not_found<H>(&mut self, handler: H) -> &mut Self
+        where H: Handler + 'static {
+        self.not_found = Box::new(handler);
+        self
+    }
+
+    /// Runs `middleware` around every request whose path is `prefix` or below it, e.g. to put `/admin` behind a login.
+    ///
+    /// This includes requests that end up as `404 Not Found` or `405 Method Not Allowed`, so the middleware can't be bypassed to find out which routes exist.
+    /// Paths are compared after decoding them, so `/%61dmin` and `//admin` are below `/admin` as well.
+    /// Middleware on a shorter prefix runs outside of middleware on a longer one, and middleware on the same prefix runs in the order it was added.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic, when `prefix` doesn't start with `/`.
+    pub fn wrap<M>(&mut self, prefix: &str, middleware: M) -> &mut Self
+        where M: Middleware + 'static {
+        assert!(prefix.starts_with('/'), "prefix {prefix:?} must start with '/'");
+        self.middleware.push((normalize(prefix), Box::new(middleware)));
+        // A stable sort keeps the order of middleware on the same prefix.
+        self.middleware.sort_by_key(|(prefix, _)| prefix.len());
+        self
+    }
+
+    fn dispatch(&self, request: &Request) -> Response {
+        let method = request.method();
+        let mut allowed = Vec::new();
+        let mut head_fallback = None;
+        for route in &self.routes {
+            let Some(params) = route.pattern.matches(request.path()) else {
+                continue;
+            };
+            if route.method == method {
+                return route.handler.handle(request, &params);
+            }
+            if method == Method::Head && route.method == Method::Get && head_fallback.is_none() {
+                head_fallback = Some((route, params));
+            }
+            allowed.push(route.method);
+        }
+
+        if let Some((route, params)) = head_fallback {
+            return route.handler.handle(request, &params);
+        }
+        if allowed.is_empty() {
+            return self.not_found.handle(request, &Params::default());
+        }
+
+        if allowed.contains(&Method::Get) {
+            allowed.push(Method::Head);
+        }
+        let mut allowed: Vec<&str> = allowed
+            .iter()
+            .map(Method::as_str)
+            .collect();
+        allowed.sort_unstable();
+        allowed.dedup();
+        Response::text(StatusCode::MethodNotAllowed, "Method Not Allowed\n")
+            .with_header("Allow", allowed.join(", "))
+    }
+}
+
+// Splits a path into the segments handlers end up working with: decoded, without empty or `.` segments, and with `..` removing the segment before it.
+// Middleware is picked by these, because `/%61dmin`, `//admin` and `/./admin` all reach the same handler as `/admin`.
+// Segments that can't be decoded are kept as they are, like literal route segments.
+fn normalize(path: &str) -> Vec<String> {
+    let mut segments = Vec::new();
+    for part in split_path(path) {
+        let decoded = percent_decode(part).unwrap_or_else(|_| part.to_string());
+        // An encoded `/` splits the segment, because `*name` params and `StaticFiles` see it as a separator too.
+        for segment in decoded.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    segments.pop();
+                }
+                _ => segments.push(segment.to_string())
+            }
+        }
+    }
+    segments
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, request: &Request, _params: &Params) -> Response {
+        // `/admin` covers `/admin` and `/admin/users`, but not `/administrator`.
+        let path = normalize(request.path());
+        let middleware: Vec<&dyn Middleware> = self.middleware
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix))
+            .map(|(_, middleware)| &**middleware)
+            .collect();
+        if middleware.is_empty() {
+            return self.dispatch(request);
+        }
+        middleware::run(&middleware, request, || self.dispatch(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, target: &str) -> Request {
+        Request::parse(&mut format!("{method} {target} HTTP/1.1\r\n\r\n").as_bytes()).unwrap()
+    }
+
+    fn echo(name: &'static str) -> impl Handler {
+        move |_: &Request, params: &Params| Response::text(StatusCode::Ok, params.get(name).unwrap_or_default())
+    }
+
+    fn body(response: Response) -> String {
+        String::from_utf8(response.body().as_bytes().unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn extracts_params() {
+        let mut router = Router::new();
+        router
+            .get("/posts/:id", echo("id"))
+            .get("/static/*rest", echo("rest"));
+
+        assert_eq!("42", body(router.handle(&request("GET", "/posts/42"), &Params::default())));
+        assert_eq!("a b", body(router.handle(&request("GET", "/posts/a%20b"), &Params::default())));
+        assert_eq!("css/site.css", body(router.handle(&request("GET", "/static/css/site.css"), &Params::default())));
+        assert_eq!("", body(router.handle(&request("GET", "/static"), &Params::default())));
+        assert_eq!(StatusCode::NotFound, router.handle(&request("GET", "/posts/42/comments"), &Params::default()).status());
+        assert_eq!(StatusCode::NotFound, router.handle(&request("GET", "/posts/"), &Params::default()).status());
+    }
+
+    #[test]
+    fn guards_prefixes_however_they_are_spelled() {
+        struct Deny;
+        impl Middleware for Deny {
+            fn before(&self, _request: &Request, _context: &mut crate::Context) -> Option<Response> {
+                Some(Response::text(StatusCode::Unauthorized, "Unauthorized\n"))
+            }
+        }
+        let mut router = Router::new();
+        router
+            .get("/*path", echo("path"))
+            .wrap("/files", Deny);
+
+        for target in ["/files/secret.txt", "/%66iles/secret.txt", "//files/secret.txt", "/./files/secret.txt", "/public/../files/secret.txt", "/files%2Fsecret.txt"] {
+            assert_eq!(StatusCode::Unauthorized, router.handle(&request("GET", target), &Params::default()).status(), "{target}");
+        }
+        assert_eq!("filesystem.txt", body(router.handle(&request("GET", "/filesystem.txt"), &Params::default())));
+    }
+
+    #[test]
+    fn rejects_other_methods_with_allow_header() {
+        let mut router = Router::new();
+        router
+            .get("/posts/:id", echo("id"))
+            .delete("/posts/:id", echo("id"));
+
+        let response = router.handle(&request("POST", "/posts/1"), &Params::default());
+        assert_eq!(StatusCode::MethodNotAllowed, response.status());
+        assert_eq!(Some("DELETE, GET, HEAD"), response.headers().get("allow"));
+
+        let response = router.handle(&request("HEAD", "/posts/1"), &Params::default());
+        assert_eq!(StatusCode::Ok, response.status());
+    }
+}
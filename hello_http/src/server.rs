@@ -0,0 +1,312 @@
+#[cfg(feature = "tls")]
+use crate::TlsConfig;
+use crate::{
+    connection,
+    log::{print_log, LogLevel, Logger},
+    ConnectionOptions,
+    Handler,
+    Response,
+    Shutdown,
+    StatusCode,
+    ThreadPool
+};
+use std::{
+    io::{self, prelude::*, ErrorKind},
+    mem,
+    net::{Shutdown as Direction, TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant}
+};
+
+// The listener doesn't block, so that the loop can notice a shutdown that was triggered by a signal.
+// This is how long it naps when there's no connection waiting.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// Clients that are turned away because the pool is full are asked to try again after this many seconds.
+const RETRY_AFTER_SECONDS: u64 = 1;
+// Turning a client away happens on the accepting thread, so a client that doesn't read must not hold it up for long.
+const REJECTION_WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+
+// What the clients on a listener speak.
+#[derive(Clone)]
+enum Protocol {
+    Http,
+    #[cfg(feature = "tls")]
+    Https(TlsConfig)
+}
+
+/// Accepts connections and answers them on a `ThreadPool` until it's told to shut down.
+///
+/// Once the shutdown starts, no new connections are accepted.
+/// Requests that are already being answered get until the end of the grace period to finish, then their connections are closed and the workers are joined.
+///
+/// If the pool's queue is full and rejects a connection, the client gets `503 Service Unavailable` with a `Retry-After` header.
+///
+/// More listeners can be added with `with_listener`, and with the `tls` feature, the same server can also answer HTTPS, see `with_https`.
+pub struct Server {
+    handler: Arc<dyn Handler>,
+    pool: ThreadPool,
+    options: Arc<ConnectionOptions>,
+    shutdown: Shutdown,
+    grace_period: Duration,
+    listeners: Vec<(TcpListener, Protocol)>,
+    logger: Arc<Logger>
+}
+
+impl Server {
+    pub fn new<H>(handler: H, pool: ThreadPool) -> Self
+        where H: Handler + 'static {
+        Server {
+            handler: Arc::new(handler),
+            pool,
+            options: Arc::new(ConnectionOptions::default()),
+            shutdown: Shutdown::new(),
+            grace_period: Duration::from_secs(10),
+            listeners: Vec::new(),
+            logger: Arc::new(print_log)
+        }
+    }
+
+    pub fn with_options(mut self, options: ConnectionOptions) -> Self {
+        self.options = Arc::new(options);
+        self
+    }
+
+    /// Uses a `Shutdown` that was created earlier, e.g. because a route needs it before the server exists.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Changes how long in-flight requests may take after the shutdown started. The default is 10 seconds.
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Sends the server's messages, like connections that couldn't be accepted, to `logger` instead of printing them.
+    /// The pool has its own logger, see `ThreadPool::with_logger`.
+    pub fn with_logger<F>(mut self, logger: F) -> Self
+        where F: Fn(LogLevel, &str) + Send + Sync + 'static {
+        self.logger = Arc::new(logger);
+        self
+    }
+
+    /// Also serves plain HTTP on `listener`, e.g. to answer on an IPv4 and an IPv6 address.
+    pub fn with_listener(mut self, listener: TcpListener) -> Self {
+        self.listeners.push((listener, Protocol::Http));
+        self
+    }
+
+    /// Also serves HTTPS on `listener`, next to the plain HTTP of the other listeners.
+    /// The handler doesn't see a difference between the two.
+    #[cfg(feature = "tls")]
+    pub fn with_https(mut self, listener: TcpListener, tls: TlsConfig) -> Self {
+        self.listeners.push((listener, Protocol::Https(tls)));
+        self
+    }
+
+    /// Returns a handle that can stop the server from another thread.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Serves connections from `listener` until the shutdown is triggered, then shuts down gracefully.
+    pub fn run(self, listener: TcpListener) -> io::Result<()> {
+        self.with_listener(listener).serve()
+    }
+
+    /// Serves connections from the listeners that were added with `with_listener` or `with_https`, like `run`.
+    pub fn serve(mut self) -> io::Result<()> {
+        if self.listeners.is_empty() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "the server has no listener to accept connections from"));
+        }
+        let listeners = mem::take(&mut self.listeners);
+        for (listener, _) in &listeners {
+            listener.set_nonblocking(true)?;
+        }
+        while !self.shutdown.is_triggered() {
+            // The loop only naps once no listener has a connection waiting, so a busy listener doesn't slow down the others.
+            let mut accepted = false;
+            for (listener, protocol) in &listeners {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        accepted = true;
+                        self.dispatch(stream, protocol);
+                    }
+                    Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+                    Err(error) if error.kind() == ErrorKind::Interrupted => accepted = true,
+                    // Errors like running out of file descriptors usually go away once some connections are closed.
+                    Err(error) => (self.logger)(LogLevel::Warn, &format!("Failed to accept a connection: {error}"))
+                }
+            }
+            if !accepted {
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+        }
+        drop(listeners);
+
+        // The shutdown might have been started by a signal, which can only set the flag, so the idle connections still need to be closed.
+        self.shutdown.trigger();
+        let deadline = Instant::now() + self.grace_period;
+        if !self.shutdown.wait_for_connections(deadline) {
+            let message = format!("Grace period is over; closing {} connection(s).", self.shutdown.active_connections());
+            (self.logger)(LogLevel::Info, &message);
+            self.shutdown.close_connections();
+        }
+        // Dropping the pool joins all workers.
+        drop(self.pool);
+        Ok(())
+    }
+
+    fn dispatch(&self, stream: TcpStream, protocol: &Protocol) {
+        // Some platforms hand out accepted streams in the listener's non-blocking mode.
+        if stream.set_nonblocking(false).is_err() {
+            return;
+        }
+        // The job owns the stream, so a second handle is needed to answer the client if the job is rejected.
+        let Ok(rejected) = stream.try_clone() else {
+            return;
+        };
+        let Ok(guard) = self.shutdown.track(&stream) else {
+            return;
+        };
+        let handler = Arc::clone(&self.handler);
+        let options = Arc::clone(&self.options);
+        let plain = matches!(protocol, Protocol::Http);
+        let protocol = protocol.clone();
+        let job = move || match protocol {
+            Protocol::Http => connection::serve(stream, &*handler, &options, Some(guard)),
+            #[cfg(feature = "tls")]
+            Protocol::Https(tls) => {
+                if let Ok(stream) = tls.accept(stream, options.header_timeout) {
+                    connection::serve(stream, &*handler, &options, Some(guard));
+                }
+            }
+        };
+        // A client that expects TLS couldn't read a plain `503`, so it's just disconnected.
+        if self.pool.try_execute(job).is_err() && plain {
+            reject(rejected);
+        }
+    }
+}
+
+fn reject(mut stream: TcpStream) {
+    let response = Response::text(StatusCode::ServiceUnavailable, "Service Unavailable\n")
+        .with_header("Retry-After", RETRY_AFTER_SECONDS.to_string())
+        .with_header("Connection", "close");
+    if stream.set_write_timeout(Some(REJECTION_WRITE_TIMEOUT)).is_err() || response.write_to(&mut stream, false).is_err() {
+        return;
+    }
+    let _ = stream.shutdown(Direction::Write);
+    // Closing a socket with unread data makes the OS reset the connection, which can destroy the response before the client reads it.
+    // Reading what already arrived avoids that without waiting for the rest.
+    if stream.set_nonblocking(true).is_ok() {
+        let mut buffer = [0; 4096];
+        while matches!(stream.read(&mut buffer), Ok(read) if read > 0) {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OverflowPolicy, Params, Request, Router};
+    use std::{
+        io::BufReader,
+        sync::{mpsc, Mutex}
+    };
+
+    #[test]
+    fn finishes_in_flight_requests_after_shutdown() {
+        let mut router = Router::new();
+        router.get("/slow", |_: &Request, _: &Params| {
+            thread::sleep(Duration::from_millis(300));
+            Response::text(StatusCode::Ok, "done")
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = Server::new(router, ThreadPool::new(2));
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || server.run(listener));
+
+        let mut slow = TcpStream::connect(address).unwrap();
+        slow.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+        let idle = TcpStream::connect(address).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        shutdown.trigger();
+
+        let mut response = String::new();
+        BufReader::new(slow).read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Connection: close"));
+        assert!(response.ends_with("done"));
+        assert_eq!(0, (&idle).read(&mut [0; 1]).unwrap());
+
+        running.join().unwrap().unwrap();
+        assert!(TcpStream::connect(address).is_err());
+    }
+
+    #[test]
+    fn reports_through_its_logger() {
+        let mut router = Router::new();
+        router.get("/slow", |_: &Request, _: &Params| {
+            thread::sleep(Duration::from_millis(300));
+            Response::text(StatusCode::Ok, "done")
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, messages) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let server = Server::new(router, ThreadPool::new(1))
+            .with_grace_period(Duration::from_millis(50))
+            .with_logger(move |level, message: &str| {
+                let _ = sender.lock().unwrap().send((level, message.to_string()));
+            });
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || server.run(listener));
+
+        let mut slow = TcpStream::connect(address).unwrap();
+        slow.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+        thread::sleep(Duration::from_millis(100));
+        shutdown.trigger();
+        running.join().unwrap().unwrap();
+
+        let (level, message) = messages.try_recv().unwrap();
+        assert_eq!(LogLevel::Info, level);
+        assert_eq!("Grace period is over; closing 1 connection(s).", message);
+    }
+
+    #[test]
+    fn turns_clients_away_when_the_pool_is_full() {
+        let mut router = Router::new();
+        router.get("/slow", |_: &Request, _: &Params| {
+            thread::sleep(Duration::from_millis(300));
+            Response::text(StatusCode::Ok, "done")
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let pool = ThreadPool::new(1)
+            .with_queue_capacity(1)
+            .with_overflow_policy(OverflowPolicy::Reject);
+        let server = Server::new(router, pool);
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || server.run(listener));
+
+        let mut busy = TcpStream::connect(address).unwrap();
+        busy.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let _queued = TcpStream::connect(address).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let mut rejected = TcpStream::connect(address).unwrap();
+        rejected.write_all(b"GET /slow HTTP/1.1\r\n\r\n").unwrap();
+
+        let mut response = String::new();
+        rejected.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable"));
+        assert!(response.contains("Retry-After: 1"));
+
+        shutdown.trigger();
+        running.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,187 @@
+use crate::{Handler, Params, Request, Response, StatusCode};
+use std::{
+    collections::HashMap,
+    io,
+    net::{Shutdown as Direction, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+        Condvar,
+        Mutex,
+        PoisonError
+    },
+    time::Instant
+};
+
+struct Tracked {
+    stream: TcpStream,
+    idle: bool
+}
+
+struct State {
+    // This lives in its own `Arc`, because signal handlers can only set a flag and need to own it.
+    triggered: Arc<AtomicBool>,
+    connections: Mutex<HashMap<u64, Tracked>>,
+    changed: Condvar,
+    next_id: AtomicU64
+}
+
+/// Tells a server to stop and keeps track of the connections it still has to finish.
+///
+/// Clones share the same state, so one clone can be handed to a signal handler or an admin endpoint while the server holds another.
+#[derive(Clone)]
+pub struct Shutdown {
+    state: Arc<State>
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Shutdown {
+            state: Arc::new(State {
+                triggered: Arc::new(AtomicBool::new(false)),
+                connections: Mutex::new(HashMap::new()),
+                changed: Condvar::new(),
+                next_id: AtomicU64::new(0)
+            })
+        }
+    }
+
+    /// Starts the shutdown.
+    /// Connections that are waiting for their next request are closed right away, busy ones after their current response.
+    pub fn trigger(&self) {
+        self.state.triggered.store(true, Ordering::SeqCst);
+        for tracked in self.connections().values() {
+            if tracked.idle {
+                // Closing only the reading half makes the blocked read return, but lets a response that is still being written go out.
+                let _ = tracked.stream.shutdown(Direction::Read);
+            }
+        }
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.state.triggered.load(Ordering::SeqCst)
+    }
+
+    /// Triggers the shutdown on `SIGINT` (Ctrl+C) and `SIGTERM`.
+    /// A second `SIGINT` exits the process immediately, in case the graceful shutdown takes too long.
+    pub fn register_signals(&self) -> io::Result<()> {
+        use signal_hook::{consts::{SIGINT, SIGTERM}, flag};
+
+        let triggered = Arc::clone(&self.state.triggered);
+        // The conditional shutdown has to be registered first, so that the first signal doesn't already count as the second one.
+        flag::register_conditional_shutdown(SIGINT, 1, Arc::clone(&triggered))?;
+        flag::register(SIGINT, Arc::clone(&triggered))?;
+        flag::register(SIGTERM, triggered)?;
+        Ok(())
+    }
+
+    /// Remembers a connection, until the returned guard is dropped.
+    pub(crate) fn track(&self, stream: &TcpStream) -> io::Result<ConnectionGuard> {
+        let id = self.state.next_id.fetch_add(1, Ordering::Relaxed);
+        let tracked = Tracked {
+            stream: stream.try_clone()?,
+            idle: true
+        };
+        self.connections().insert(id, tracked);
+        Ok(ConnectionGuard {
+            id,
+            shutdown: self.clone()
+        })
+    }
+
+    /// Waits until every tracked connection is finished or `deadline` has passed.
+    /// Returns `true` if all connections finished in time.
+    pub fn wait_for_connections(&self, deadline: Instant) -> bool {
+        let mut connections = self.connections();
+        while !connections.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            connections = self.state.changed
+                .wait_timeout(connections, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        true
+    }
+
+    /// Closes every connection that is still open, no matter what it's doing.
+    pub fn close_connections(&self) {
+        for tracked in self.connections().values() {
+            let _ = tracked.stream.shutdown(Direction::Both);
+        }
+    }
+
+    pub fn active_connections(&self) -> usize {
+        self.connections().len()
+    }
+
+    fn connections(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Tracked>> {
+        // The map stays consistent even if a thread panicked while holding the lock, so the poison can be ignored.
+        self.state.connections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Keeps a connection registered with a `Shutdown` while it's alive.
+pub(crate) struct ConnectionGuard {
+    id: u64,
+    shutdown: Shutdown
+}
+
+impl ConnectionGuard {
+    /// Marks the connection as waiting for a request (`true`) or working on one (`false`).
+    /// Returns `false` if the connection should be closed instead of waiting for the next request.
+    pub(crate) fn set_idle(&self, idle: bool) -> bool {
+        if let Some(tracked) = self.shutdown.connections().get_mut(&self.id) {
+            tracked.idle = idle;
+        }
+        // Checking after the update means a shutdown can't slip in between without either side noticing.
+        !(idle && self.shutdown.is_triggered())
+    }
+
+    pub(crate) fn is_shutting_down(&self) -> bool {
+        self.shutdown.is_triggered()
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.shutdown.connections().remove(&self.id);
+        self.shutdown.state.changed.notify_all();
+    }
+}
+
+/// A handler that starts the shutdown, e.g. for `POST /admin/shutdown`.
+///
+/// Only requests from the same machine are accepted, everyone else gets `403 Forbidden`.
+pub struct ShutdownHandler {
+    shutdown: Shutdown
+}
+
+impl ShutdownHandler {
+    pub fn new(shutdown: Shutdown) -> Self {
+        ShutdownHandler { shutdown }
+    }
+}
+
+impl Handler for ShutdownHandler {
+    fn handle(&self, request: &Request, _params: &Params) -> Response {
+        let is_local = request
+            .peer_addr()
+            .is_some_and(|address| address.ip().is_loopback());
+        if !is_local {
+            return Response::text(StatusCode::Forbidden, "Forbidden\n");
+        }
+        self.shutdown.trigger();
+        Response::text(StatusCode::Accepted, "Shutting down\n").with_header("Connection", "close")
+    }
+}
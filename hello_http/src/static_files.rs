@@ -0,0 +1,501 @@
+use crate::{
+    compression::{accepts_gzip, vary_on_encoding},
+    date::{format_http_date, parse_http_date},
+    mime,
+    range::{parse_ranges, Ranges},
+    request::percent_decode,
+    Body,
+    Handler,
+    Method,
+    Params,
+    Request,
+    Response,
+    StatusCode
+};
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::{self, prelude::*, Cursor, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH}
+};
+
+/// Serves the files below a document root.
+///
+/// Mount it on a route with a `*path` segment, e.g. `router.get("/static/*path", files)`.
+/// Without a `path` parameter the whole request path is used instead.
+///
+/// Paths that try to leave the root are rejected with `403 Forbidden`, no matter if they use `..`, an encoded `%2e%2e` or a symlink pointing somewhere else.
+///
+/// Files are sent with `ETag` and `Last-Modified`, so clients can revalidate them with `If-None-Match` or `If-Modified-Since` and get a `304 Not Modified`.
+/// `Range` requests are answered with `206 Partial Content`, which allows resuming downloads.
+///
+/// If a file has a precompressed sibling, e.g. `app.js.gz` next to `app.js`, clients that accept gzip get that one instead.
+pub struct StaticFiles {
+    root: PathBuf,
+    index: String,
+    listing: bool,
+    not_found_page: Option<PathBuf>
+}
+
+impl StaticFiles {
+    /// Creates a handler for the directory `root`, which must exist.
+    pub fn new(root: impl AsRef<Path>) -> io::Result<Self> {
+        // Symlinks are resolved once here, so every resolved file can be compared against the real root.
+        let root = fs::canonicalize(root)?;
+        if !root.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a directory", root.display())));
+        }
+        Ok(StaticFiles {
+            root,
+            index: String::from("index.html"),
+            listing: false,
+            not_found_page: None
+        })
+    }
+
+    /// Changes the file that is served for a directory. The default is `index.html`.
+    pub fn with_index(mut self, index: impl Into<String>) -> Self {
+        self.index = index.into();
+        self
+    }
+
+    /// Lists the contents of directories without an index file as HTML.
+    pub fn with_directory_listing(mut self, enabled: bool) -> Self {
+        self.listing = enabled;
+        self
+    }
+
+    /// Serves `page` (relative to the root) as the body of `404 Not Found` responses.
+    pub fn with_not_found_page(mut self, page: impl AsRef<Path>) -> Self {
+        self.not_found_page = Some(self.root.join(page));
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Maps a decoded, `/`-separated path to a file below the root.
+    fn resolve(&self, relative: &str) -> Result<PathBuf, StatusCode> {
+        let mut path = self.root.clone();
+        for component in relative.split('/') {
+            match component {
+                "" | "." => continue,
+                ".." => return Err(StatusCode::Forbidden),
+                // A backslash is a separator on Windows and a NUL byte would cut the path short.
+                _ if component.contains(['\\', '\0']) => return Err(StatusCode::Forbidden),
+                _ => path.push(component)
+            }
+        }
+        let path = fs::canonicalize(&path).map_err(|_| StatusCode::NotFound)?;
+        if !path.starts_with(&self.root) {
+            return Err(StatusCode::Forbidden);
+        }
+        Ok(path)
+    }
+
+    /// Finds the `.gz` sibling of a file, which has to be below the root like any other file.
+    fn precompressed(&self, path: &Path) -> Option<PathBuf> {
+        let mut name = path.file_name()?.to_os_string();
+        name.push(".gz");
+        let sibling = fs::canonicalize(path.with_file_name(name)).ok()?;
+        (sibling.starts_with(&self.root) && sibling.is_file()).then_some(sibling)
+    }
+
+    fn error(&self, status: StatusCode) -> Response {
+        let page = self.not_found_page
+            .as_ref()
+            .filter(|_| status == StatusCode::NotFound)
+            .and_then(|page| File::open(page).and_then(Body::file).ok());
+        match page {
+            Some(body) => Response::new(status)
+                .with_header("Content-Type", "text/html; charset=utf-8")
+                .with_body(body),
+            None => Response::text(status, format!("{}\n", status.reason()))
+        }
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, request: &Request, params: &Params) -> Response {
+        let relative = match params.get("path") {
+            Some(path) => path.to_string(),
+            None => match percent_decode(request.path()) {
+                Ok(path) => path,
+                Err(_) => return self.error(StatusCode::BadRequest)
+            }
+        };
+        let mut path = match self.resolve(&relative) {
+            Ok(path) => path,
+            Err(status) => return self.error(status)
+        };
+
+        if path.is_dir() {
+            // Relative links in an index page only work if the directory URL ends with a slash.
+            if !request.path().ends_with('/') {
+                let location = match request.target().split_once('?') {
+                    Some((path, query)) => format!("{path}/?{query}"),
+                    None => format!("{}/", request.path())
+                };
+                return Response::new(StatusCode::MovedPermanently).with_header("Location", location);
+            }
+            let index = path.join(&self.index);
+            if index.is_file() {
+                path = match self.resolve(&format!("{relative}/{}", self.index)) {
+                    Ok(path) => path,
+                    Err(status) => return self.error(status)
+                };
+            } else if self.listing {
+                return match listing(&path, request.path()) {
+                    Ok(html) => Response::html(StatusCode::Ok, html),
+                    Err(_) => self.error(StatusCode::InternalServerError)
+                };
+            } else {
+                return self.error(StatusCode::NotFound);
+            }
+        }
+
+        let content_type = mime::content_type(&path);
+        let precompressed = self.precompressed(&path);
+        // Ranges of the compressed file would confuse clients that asked for ranges of the file they know, so they get the original.
+        let response = match &precompressed {
+            Some(sibling) if accepts_gzip(request) && !request.headers().contains("Range") => {
+                serve_file(request, sibling, content_type).map(|response| response.with_header("Content-Encoding", "gzip"))
+            }
+            _ => serve_file(request, &path, content_type)
+        };
+        match response {
+            Ok(mut response) => {
+                if precompressed.is_some() {
+                    vary_on_encoding(&mut response);
+                }
+                response
+            }
+            Err(_) => self.error(StatusCode::NotFound)
+        }
+    }
+}
+
+/// Serves the file at `path` as `content_type`, which is the type of the original file if `path` is a compressed copy.
+fn serve_file(request: &Request, path: &Path, content_type: &'static str) -> io::Result<Response> {
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let length = metadata.len();
+    let modified = metadata.modified().ok();
+    let etag = entity_tag(length, modified);
+
+    let mut response = Response::new(StatusCode::Ok)
+        .with_header("ETag", etag.as_str())
+        .with_header("Accept-Ranges", "bytes");
+    if let Some(modified) = modified {
+        response = response.with_header("Last-Modified", format_http_date(modified));
+    }
+    if is_not_modified(request, &etag, modified) {
+        return Ok(response.with_status(StatusCode::NotModified));
+    }
+    let response = response.with_header("Content-Type", content_type);
+
+    // Only `GET` requests can ask for ranges, and `If-Range` makes sure the ranges still refer to the version of the file the client already has.
+    let range = request
+        .header("Range")
+        .filter(|_| request.method() == Method::Get)
+        .filter(|_| if_range_matches(request, &etag, modified));
+    let ranges = match range {
+        Some(range) => parse_ranges(range, length),
+        None => Ranges::Ignore
+    };
+    match ranges {
+        Ranges::Ignore => Ok(response.with_body(Body::File { file, length })),
+        Ranges::Unsatisfiable => Ok(response
+            .with_status(StatusCode::RangeNotSatisfiable)
+            .with_header("Content-Range", format!("bytes */{length}"))),
+        Ranges::Satisfiable(ranges) if ranges.len() == 1 => {
+            let range = &ranges[0];
+            file.seek(SeekFrom::Start(range.start))?;
+            Ok(response
+                .with_status(StatusCode::PartialContent)
+                .with_header("Content-Range", format!("bytes {}-{}/{length}", range.start, range.end - 1))
+                .with_body(Body::File { file, length: range.end - range.start }))
+        }
+        Ranges::Satisfiable(ranges) => {
+            let boundary = boundary();
+            let body = multipart_body(path, &ranges, length, content_type, &boundary)?;
+            Ok(response
+                .with_status(StatusCode::PartialContent)
+                .with_header("Content-Type", format!("multipart/byteranges; boundary={boundary}"))
+                .with_body(body))
+        }
+    }
+}
+
+fn entity_tag(length: u64, modified: Option<SystemTime>) -> String {
+    let modified = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or(Duration::ZERO);
+    format!("\"{:x}-{:x}\"", modified.as_nanos(), length)
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+fn is_not_modified(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    if !matches!(request.method(), Method::Get | Method::Head) {
+        return false;
+    }
+    // `If-Modified-Since` has to be ignored as soon as `If-None-Match` is present, because entity tags are more precise.
+    if let Some(if_none_match) = request.header("If-None-Match") {
+        return if_none_match
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || weak_eq(tag, etag));
+    }
+    let since = request
+        .header("If-Modified-Since")
+        .and_then(parse_http_date);
+    match (since, modified) {
+        // Dates in headers only have a precision of one second.
+        (Some(since), Some(modified)) => seconds(modified) <= seconds(since),
+        _ => false
+    }
+}
+
+fn if_range_matches(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    let Some(if_range) = request.header("If-Range").map(str::trim) else {
+        return true;
+    };
+    if if_range.starts_with('"') {
+        // Weak tags can never be used for ranges, so only a strong, identical tag counts.
+        return if_range == etag;
+    }
+    match (parse_http_date(if_range), modified) {
+        (Some(date), Some(modified)) => seconds(date) == seconds(modified),
+        _ => false
+    }
+}
+
+fn weak_eq(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
+
+fn boundary() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_nanos();
+    format!("hello_http_{nanos:x}")
+}
+
+/// Puts together a `multipart/byteranges` body, which reads every range from its own file handle as it is being sent.
+fn multipart_body(path: &Path, ranges: &[Range<u64>], length: u64, content_type: &str, boundary: &str) -> io::Result<Body> {
+    let mut reader: Box<dyn Read + Send> = Box::new(io::empty());
+    let mut total = 0;
+    for range in ranges {
+        let head = format!(
+            "\r\n--{boundary}\r\nContent-Type: {content_type}\r\nContent-Range: bytes {}-{}/{length}\r\n\r\n",
+            range.start,
+            range.end - 1
+        );
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(range.start))?;
+        let part_length = range.end - range.start;
+        total += head.len() as u64 + part_length;
+        reader = Box::new(reader
+            .chain(Cursor::new(head))
+            .chain(file.take(part_length)));
+    }
+    let tail = format!("\r\n--{boundary}--\r\n");
+    total += tail.len() as u64;
+    Ok(Body::Reader {
+        reader: Box::new(reader.chain(Cursor::new(tail))),
+        length: total
+    })
+}
+
+fn listing(directory: &Path, request_path: &str) -> io::Result<String> {
+    let mut names: Vec<(String, bool)> = fs::read_dir(directory)?
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let is_dir = entry.file_type().ok()?.is_dir();
+            Some((entry.file_name().into_string().ok()?, is_dir))
+        })
+        .collect();
+    names.sort();
+
+    let title = escape_html(request_path);
+    let mut html = format!("<!DOCTYPE html>\n<html lang=\"en\">\n    <head>\n        <meta charset=\"utf-8\">\n        <title>Index of {title}</title>\n    </head>\n    <body>\n        <h1>Index of {title}</h1>\n        <ul>\n");
+    if request_path != "/" {
+        html.push_str("            <li><a href=\"../\">../</a></li>\n");
+    }
+    for (name, is_dir) in names {
+        let slash = if is_dir { "/" } else { "" };
+        let _ = writeln!(html, "            <li><a href=\"{}{slash}\">{}{slash}</a></li>", percent_encode(&name), escape_html(&name));
+    }
+    html.push_str("        </ul>\n    </body>\n</html>\n");
+    Ok(html)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            let _ = write!(encoded, "%{byte:02X}");
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Router;
+    use std::env;
+
+    fn document_root(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("hello_http-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("index.html"), "<h1>Home</h1>").unwrap();
+        fs::write(root.join("docs/guide.txt"), "guide").unwrap();
+        root
+    }
+
+    fn get(router: &Router, target: &str) -> Response {
+        get_with(router, target, "")
+    }
+
+    fn get_with(router: &Router, target: &str, headers: &str) -> Response {
+        let request = Request::parse(&mut format!("GET {target} HTTP/1.1\r\n{headers}\r\n").as_bytes()).unwrap();
+        router.handle(&request, &Params::default())
+    }
+
+    fn body(response: Response) -> String {
+        let mut output = Vec::new();
+        response.write_to(&mut output, false).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        output.split_once("\r\n\r\n").unwrap().1.to_string()
+    }
+
+    #[test]
+    fn serves_files_and_indexes() {
+        let root = document_root("serve");
+        let mut router = Router::new();
+        router.get("/files/*path", StaticFiles::new(&root).unwrap().with_directory_listing(true));
+
+        let response = get(&router, "/files/docs/guide.txt");
+        assert_eq!(StatusCode::Ok, response.status());
+        assert_eq!(Some("text/plain; charset=utf-8"), response.headers().get("Content-Type"));
+        assert_eq!(Some(5), response.body().len());
+
+        assert_eq!(Some("text/html; charset=utf-8"), get(&router, "/files/").headers().get("Content-Type"));
+        assert_eq!(Some("/files/docs/"), get(&router, "/files/docs").headers().get("Location"));
+        let listing = get(&router, "/files/docs/");
+        let html = String::from_utf8(listing.body().as_bytes().unwrap().to_vec()).unwrap();
+        assert!(html.contains("<a href=\"guide.txt\">guide.txt</a>"));
+        assert_eq!(StatusCode::NotFound, get(&router, "/files/missing.txt").status());
+    }
+
+    #[test]
+    fn rejects_traversal() {
+        let root = document_root("traversal");
+        let mut router = Router::new();
+        router.get("/files/*path", StaticFiles::new(root.join("docs")).unwrap());
+
+        assert_eq!(StatusCode::Forbidden, get(&router, "/files/../index.html").status());
+        assert_eq!(StatusCode::Forbidden, get(&router, "/files/%2e%2e/index.html").status());
+        assert_eq!(StatusCode::Forbidden, get(&router, "/files/%2E%2E%2Findex.html").status());
+        assert_eq!(StatusCode::Forbidden, get(&router, "/files/..%5Cindex.html").status());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("index.html"), root.join("docs/escape.html")).unwrap();
+            assert_eq!(StatusCode::Forbidden, get(&router, "/files/escape.html").status());
+        }
+    }
+
+    #[test]
+    fn answers_conditional_requests() {
+        let root = document_root("conditional");
+        let mut router = Router::new();
+        router.get("/*path", StaticFiles::new(&root).unwrap());
+
+        let response = get(&router, "/docs/guide.txt");
+        let etag = response.headers().get("ETag").unwrap().to_string();
+        let last_modified = response.headers().get("Last-Modified").unwrap().to_string();
+
+        let response = get_with(&router, "/docs/guide.txt", &format!("If-None-Match: \"other\", {etag}\r\n"));
+        assert_eq!(StatusCode::NotModified, response.status());
+        let response = get_with(&router, "/docs/guide.txt", &format!("If-Modified-Since: {last_modified}\r\n"));
+        assert_eq!(StatusCode::NotModified, response.status());
+        let response = get_with(&router, "/docs/guide.txt", "If-Modified-Since: Thu, 01 Jan 1970 00:00:00 GMT\r\n");
+        assert_eq!(StatusCode::Ok, response.status());
+    }
+
+    #[test]
+    fn answers_range_requests() {
+        let root = document_root("range");
+        let mut router = Router::new();
+        router.get("/*path", StaticFiles::new(&root).unwrap());
+
+        let response = get_with(&router, "/docs/guide.txt", "Range: bytes=1-3\r\n");
+        assert_eq!(StatusCode::PartialContent, response.status());
+        assert_eq!(Some("bytes 1-3/5"), response.headers().get("Content-Range"));
+        assert_eq!("uid", body(response));
+
+        let response = get_with(&router, "/docs/guide.txt", "Range: bytes=0-0, -1\r\n");
+        let content_type = response.headers().get("Content-Type").unwrap().to_string();
+        let boundary = content_type.strip_prefix("multipart/byteranges; boundary=").unwrap().to_string();
+        let expected = format!(
+            "\r\n--{boundary}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 0-0/5\r\n\r\ng\
+             \r\n--{boundary}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Range: bytes 4-4/5\r\n\r\ne\
+             \r\n--{boundary}--\r\n"
+        );
+        assert_eq!(expected, body(response));
+
+        let response = get_with(&router, "/docs/guide.txt", "Range: bytes=10-\r\n");
+        assert_eq!(StatusCode::RangeNotSatisfiable, response.status());
+        assert_eq!(Some("bytes */5"), response.headers().get("Content-Range"));
+
+        let response = get_with(&router, "/docs/guide.txt", "Range: bytes=1-3\r\nIf-Range: \"outdated\"\r\n");
+        assert_eq!(StatusCode::Ok, response.status());
+
+        // An empty file has no bytes a range could cover, not even the last few.
+        fs::write(root.join("empty.txt"), "").unwrap();
+        let response = get_with(&router, "/empty.txt", "Range: bytes=-5\r\n");
+        assert_eq!(StatusCode::RangeNotSatisfiable, response.status());
+        assert_eq!(Some("bytes */0"), response.headers().get("Content-Range"));
+    }
+
+    #[test]
+    fn serves_precompressed_siblings() {
+        let root = document_root("precompressed");
+        fs::write(root.join("docs/guide.txt.gz"), "not really gzip").unwrap();
+        let mut router = Router::new();
+        router.get("/*path", StaticFiles::new(&root).unwrap());
+
+        let response = get_with(&router, "/docs/guide.txt", "Accept-Encoding: br, gzip;q=0.8\r\n");
+        assert_eq!(Some("gzip"), response.headers().get("Content-Encoding"));
+        assert_eq!(Some("text/plain; charset=utf-8"), response.headers().get("Content-Type"));
+        assert_eq!(Some("Accept-Encoding"), response.headers().get("Vary"));
+        assert_eq!("not really gzip", body(response));
+
+        let response = get_with(&router, "/docs/guide.txt", "Accept-Encoding: gzip;q=0\r\n");
+        assert_eq!(None, response.headers().get("Content-Encoding"));
+        assert_eq!(Some("Accept-Encoding"), response.headers().get("Vary"));
+        assert_eq!("guide", body(response));
+        assert_eq!(None, get(&router, "/index.html").headers().get("Vary"));
+    }
+}
@@ -0,0 +1,114 @@
+use rustls::{crypto::ring, ServerConfig, ServerConnection, StreamOwned};
+use std::{
+    error::Error,
+    fmt,
+    fs,
+    io,
+    net::TcpStream,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant}
+};
+
+/// A certificate and its private key, which a `Server` uses to answer HTTPS connections.
+///
+/// Only TLS 1.2 and 1.3 are offered, with the defaults `rustls` considers safe.
+#[derive(Clone)]
+pub struct TlsConfig {
+    config: Arc<ServerConfig>
+}
+
+impl TlsConfig {
+    /// Loads the certificate chain and the private key from PEM files, e.g. the `fullchain.pem` and `privkey.pem` of Let's Encrypt.
+    pub fn from_pem_files(certificate: impl AsRef<Path>, key: impl AsRef<Path>) -> Result<Self, TlsError> {
+        let certificate = fs::read(certificate).map_err(TlsError::Io)?;
+        let key = fs::read(key).map_err(TlsError::Io)?;
+        Self::from_pem(&certificate, &key)
+    }
+
+    /// Reads the certificate chain (the server's certificate first) and a PKCS#8, PKCS#1 or SEC1 private key.
+    pub fn from_pem(certificate: &[u8], key: &[u8]) -> Result<Self, TlsError> {
+        let certificates = rustls_pemfile::certs(&mut &*certificate)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(TlsError::Io)?;
+        if certificates.is_empty() {
+            return Err(TlsError::NoCertificate);
+        }
+        let key = rustls_pemfile::private_key(&mut &*key)
+            .map_err(TlsError::Io)?
+            .ok_or(TlsError::NoPrivateKey)?;
+        // The provider is chosen explicitly, so it doesn't matter what other crates in the same program enabled in `rustls`.
+        let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(TlsError::Rustls)?
+            .with_no_client_auth()
+            .with_single_cert(certificates, key)
+            .map_err(TlsError::Rustls)?;
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(TlsConfig { config: Arc::new(config) })
+    }
+
+    /// Wraps an accepted connection, once the handshake finished within `timeout`.
+    pub(crate) fn accept(&self, mut stream: TcpStream, timeout: Duration) -> io::Result<StreamOwned<ServerConnection, TcpStream>> {
+        let mut connection = ServerConnection::new(Arc::clone(&self.config)).map_err(io::Error::other)?;
+        // Like the request head, the whole handshake has to be done by the deadline, so a client can't stretch it by sending one byte at a time.
+        let deadline = Instant::now() + timeout;
+        stream.set_write_timeout(Some(timeout))?;
+        while connection.is_handshaking() {
+            while connection.wants_write() {
+                connection.write_tls(&mut stream)?;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            // A timeout of zero would mean waiting forever.
+            if remaining.is_zero() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            stream.set_read_timeout(Some(remaining))?;
+            if connection.read_tls(&mut stream)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            if let Err(error) = connection.process_new_packets() {
+                // The client is told why with an alert, if it still listens.
+                let _ = connection.write_tls(&mut stream);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+            }
+        }
+        // With TLS 1.2, the server's last message only goes out after the client's.
+        while connection.wants_write() {
+            connection.write_tls(&mut stream)?;
+        }
+        Ok(StreamOwned::new(connection, stream))
+    }
+}
+
+#[derive(Debug)]
+pub enum TlsError {
+    Io(io::Error),
+    /// The certificate file doesn't contain a single `CERTIFICATE` block.
+    NoCertificate,
+    /// The key file doesn't contain a private key in a format `rustls` understands.
+    NoPrivateKey,
+    /// The key doesn't fit the certificate, or isn't supported.
+    Rustls(rustls::Error)
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::Io(error) => write!(f, "failed to read the certificate or the key: {error}"),
+            TlsError::NoCertificate => write!(f, "no certificate was found"),
+            TlsError::NoPrivateKey => write!(f, "no private key was found"),
+            TlsError::Rustls(error) => write!(f, "the certificate or the key can't be used: {error}")
+        }
+    }
+}
+
+impl Error for TlsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TlsError::Io(error) => Some(error),
+            TlsError::Rustls(error) => Some(error),
+            _ => None
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use crate::{Handler, Params, Request, Response, StatusCode, Version};
+
+#[derive(Debug, PartialEq, Eq)]
+enum HostPattern {
+    Exact(String),
+    // `*.example.com` is stored as `.example.com`, so it matches every subdomain, but not `example.com` itself.
+    Wildcard(String)
+}
+
+impl HostPattern {
+    fn parse(pattern: &str) -> Option<Self> {
+        let pattern = pattern.to_ascii_lowercase();
+        match pattern.strip_prefix('*') {
+            Some(suffix) if suffix.starts_with('.') && is_host_name(&suffix[1..]) => Some(HostPattern::Wildcard(suffix.to_string())),
+            Some(_) => None,
+            None if is_host_name(&pattern) => Some(HostPattern::Exact(pattern)),
+            None => None
+        }
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        match self {
+            HostPattern::Exact(name) => host == name,
+            HostPattern::Wildcard(suffix) => host.len() > suffix.len() && host.ends_with(suffix.as_str())
+        }
+    }
+}
+
+/// Whether `name` can be used as a host in `VirtualHosts::host`, either as a name like `example.com` or as a wildcard like `*.example.com`.
+pub fn is_host_pattern(name: &str) -> bool {
+    HostPattern::parse(name).is_some()
+}
+
+// Host names are letters, digits and hyphens between dots, but `_` shows up in internal names often enough to be allowed too.
+// IP addresses pass as well, because they're made of the same characters, or of hex digits and colons in brackets.
+fn is_host_name(name: &str) -> bool {
+    if let Some(address) = name.strip_prefix('[').and_then(|name| name.strip_suffix(']')) {
+        return !address.is_empty() && address.chars().all(|c| c.is_ascii_hexdigit() || c == ':' || c == '.');
+    }
+    !name.is_empty() && name
+        .split('.')
+        .all(|label| !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+}
+
+// Turns a `Host` value like `Example.com.:8080` into the name it is matched by, `example.com`.
+// `Err` means the value is malformed, and `Ok(None)` that the client didn't name a host.
+fn host_name(value: &str) -> Result<Option<String>, ()> {
+    let value = value.trim().to_ascii_lowercase();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    // The port is whatever follows the last colon, unless that colon belongs to an IPv6 address.
+    let (name, port) = match value.rfind(':') {
+        Some(colon) if !value[colon..].contains(']') => (&value[..colon], Some(&value[colon + 1..])),
+        _ => (value.as_str(), None)
+    };
+    if port.is_some_and(|port| !port.chars().all(|c| c.is_ascii_digit())) {
+        return Err(());
+    }
+    // `example.com.` is the fully qualified form of `example.com`.
+    let name = name.strip_suffix('.').unwrap_or(name);
+    if is_host_name(name) {
+        Ok(Some(name.to_string()))
+    } else {
+        Err(())
+    }
+}
+
+/// Serves several sites from one server, picking the handler by the `Host` header of the request.
+///
+/// Names are matched without the port and regardless of case, and exact names win over wildcards.
+/// Of several wildcards, the longest one wins, so `*.api.example.com` is picked over `*.example.com` for `v1.api.example.com`.
+///
+/// Following HTTP/1.1, requests with a missing, repeated or malformed `Host` header are answered with `400 Bad Request`.
+/// HTTP/1.0 clients aren't required to send the header, so their requests go to the default handler.
+/// Requests for a host nobody serves go to the default handler too, or get a `421 Misdirected Request` without one.
+pub struct VirtualHosts {
+    hosts: Vec<(Vec<HostPattern>, Box<dyn Handler>)>,
+    default: Option<Box<dyn Handler>>
+}
+
+impl VirtualHosts {
+    pub fn new() -> Self {
+        VirtualHosts {
+            hosts: Vec::new(),
+            default: None
+        }
+    }
+
+    /// Registers `handler` for requests to any of `names`, e.g. `["example.com", "www.example.com"]` or `["*.example.com"]`.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic, when one of `names` is neither a host name nor a wildcard like `*.example.com`.
+    pub fn host<H>(&mut self, names: &[&str], handler: H) -> &mut Self
+        where H: Handler + 'static {
+        let patterns = names
+            .iter()
+            .map(|name| HostPattern::parse(name).unwrap_or_else(|| panic!("{name:?} is not a host name like example.com or *.example.com")))
+            .collect();
+        self.hosts.push((patterns, Box::new(handler)));
+        self
+    }
+
+    /// Sets the handler for requests to hosts that weren't registered, and for HTTP/1.0 requests without a `Host`.
+    pub fn default_host<H>(&mut self, handler: H) -> &mut Self
+        where H: Handler + 'static {
+        self.default = Some(Box::new(handler));
+        self
+    }
+
+    fn find(&self, host: &str) -> Option<&dyn Handler> {
+        let exact = self.hosts
+            .iter()
+            .find(|(patterns, _)| patterns.contains(&HostPattern::Exact(host.to_string())));
+        if let Some((_, handler)) = exact {
+            return Some(&**handler);
+        }
+        // Of equally long wildcards, the one registered first wins.
+        let mut best: Option<(usize, &dyn Handler)> = None;
+        for (patterns, handler) in &self.hosts {
+            for pattern in patterns {
+                if let HostPattern::Wildcard(suffix) = pattern {
+                    if pattern.matches(host) && best.is_none_or(|(length, _)| suffix.len() > length) {
+                        best = Some((suffix.len(), &**handler));
+                    }
+                }
+            }
+        }
+        best.map(|(_, handler)| handler)
+    }
+}
+
+impl Default for VirtualHosts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Handler for VirtualHosts {
+    fn handle(&self, request: &Request, params: &Params) -> Response {
+        let mut values = request.headers().get_all("Host");
+        let host = match (values.next(), values.next()) {
+            (Some(value), None) => match host_name(value) {
+                Ok(host) => host,
+                Err(()) => return Response::text(StatusCode::BadRequest, "Bad Request: malformed Host header\n")
+            },
+            (None, _) if request.version() == Version::Http10 => None,
+            (None, _) => return Response::text(StatusCode::BadRequest, "Bad Request: missing Host header\n"),
+            (Some(_), Some(_)) => return Response::text(StatusCode::BadRequest, "Bad Request: more than one Host header\n")
+        };
+        let handler = host
+            .and_then(|host| self.find(&host))
+            .or(self.default.as_deref());
+        match handler {
+            Some(handler) => handler.handle(request, params),
+            None => Response::text(StatusCode::MisdirectedRequest, "Misdirected Request\n")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    fn get(hosts: &VirtualHosts, request: &str) -> (StatusCode, String) {
+        let request = Request::parse(&mut BufReader::new(request.as_bytes())).unwrap();
+        let response = hosts.handle(&request, &Params::default());
+        let body = String::from_utf8(response.body().as_bytes().unwrap_or_default().to_vec()).unwrap();
+        (response.status(), body)
+    }
+
+    fn site(name: &'static str) -> impl Handler {
+        move |_: &Request, _: &Params| Response::text(StatusCode::Ok, name)
+    }
+
+    #[test]
+    fn picks_exact_names_before_the_longest_wildcard() {
+        let mut hosts = VirtualHosts::new();
+        hosts
+            .host(&["*.example.com"], site("wildcard"))
+            .host(&["*.api.example.com"], site("api"))
+            .host(&["example.com", "www.example.com"], site("main"))
+            .default_host(site("default"));
+
+        assert_eq!("main", get(&hosts, "GET / HTTP/1.1\r\nHost: Example.COM.:8080\r\n\r\n").1);
+        assert_eq!("main", get(&hosts, "GET / HTTP/1.1\r\nHost: www.example.com\r\n\r\n").1);
+        assert_eq!("wildcard", get(&hosts, "GET / HTTP/1.1\r\nHost: blog.example.com\r\n\r\n").1);
+        assert_eq!("api", get(&hosts, "GET / HTTP/1.1\r\nHost: v1.api.example.com\r\n\r\n").1);
+        assert_eq!("default", get(&hosts, "GET / HTTP/1.1\r\nHost: example.org\r\n\r\n").1);
+        assert_eq!("default", get(&hosts, "GET / HTTP/1.1\r\nHost: [::1]:7878\r\n\r\n").1);
+    }
+
+    #[test]
+    fn follows_the_http_rules_for_the_host_header() {
+        let mut hosts = VirtualHosts::new();
+        hosts.host(&["example.com"], site("main"));
+
+        assert_eq!(StatusCode::BadRequest, get(&hosts, "GET / HTTP/1.1\r\n\r\n").0);
+        assert_eq!(StatusCode::BadRequest, get(&hosts, "GET / HTTP/1.1\r\nHost: example.com\r\nHost: example.org\r\n\r\n").0);
+        assert_eq!(StatusCode::BadRequest, get(&hosts, "GET / HTTP/1.1\r\nHost: exa mple.com\r\n\r\n").0);
+        assert_eq!(StatusCode::BadRequest, get(&hosts, "GET / HTTP/1.1\r\nHost: example.com:http\r\n\r\n").0);
+        assert_eq!(StatusCode::MisdirectedRequest, get(&hosts, "GET / HTTP/1.1\r\nHost: example.org\r\n\r\n").0);
+        // Without a default, there's nobody to answer HTTP/1.0 requests that don't name a host.
+        assert_eq!(StatusCode::MisdirectedRequest, get(&hosts, "GET / HTTP/1.0\r\n\r\n").0);
+
+        hosts.default_host(site("default"));
+        assert_eq!("default", get(&hosts, "GET / HTTP/1.0\r\n\r\n").1);
+        assert!(!is_host_pattern("*example.com"));
+        assert!(!is_host_pattern("www.*.com"));
+    }
+}
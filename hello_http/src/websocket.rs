@@ -0,0 +1,752 @@
+use crate::{
+    connection::{Transport, Upgraded},
+    shutdown::ConnectionGuard,
+    Handler,
+    Method,
+    Params,
+    Request,
+    Response,
+    StatusCode,
+    Version
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha1::{Digest, Sha1};
+use std::{
+    io::{self, prelude::*, ErrorKind},
+    net::{Shutdown as Direction, SocketAddr},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender, TryRecvError},
+        Arc,
+        Mutex,
+        MutexGuard,
+        PoisonError
+    },
+    thread,
+    time::{Duration, Instant}
+};
+
+// Appended to the client's key, so that a server that doesn't know WebSocket can't accept the handshake by accident.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// How long the connection thread naps when none of its connections had anything to read.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+// How long a client gets to answer a close frame, before the connection is simply dropped.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+// At most this much is read from one connection in a row, so a busy client doesn't keep the others waiting.
+const READ_LIMIT: usize = 64 * 1024;
+
+/// A complete message, which may have arrived in several fragments.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>)
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Self {
+        Message::Text(text)
+    }
+}
+
+impl From<&str> for Message {
+    fn from(text: &str) -> Self {
+        Message::Text(text.to_string())
+    }
+}
+
+impl From<Vec<u8>> for Message {
+    fn from(bytes: Vec<u8>) -> Self {
+        Message::Binary(bytes)
+    }
+}
+
+/// Why a WebSocket was closed, as listed in RFC 6455, section 7.4.1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CloseCode {
+    /// 1000: the purpose of the connection was fulfilled.
+    Normal,
+    /// 1001: the server is shutting down, or the browser left the page.
+    GoingAway,
+    /// 1002
+    ProtocolError,
+    /// 1003: e.g. a binary message for an endpoint that only understands text.
+    Unsupported,
+    /// 1005: the close frame didn't contain a code. This is never sent.
+    NoStatus,
+    /// 1006: the connection was lost without a close frame. This is never sent.
+    Abnormal,
+    /// 1007: a text message wasn't valid UTF-8.
+    InvalidPayload,
+    /// 1008
+    PolicyViolation,
+    /// 1009
+    TooBig,
+    /// 1011: the server ran into a problem it couldn't handle.
+    InternalError,
+    /// Any other code, e.g. one from 3000 to 4999, which applications can define themselves.
+    Other(u16)
+}
+
+impl CloseCode {
+    pub fn code(&self) -> u16 {
+        match self {
+            CloseCode::Normal => 1000,
+            CloseCode::GoingAway => 1001,
+            CloseCode::ProtocolError => 1002,
+            CloseCode::Unsupported => 1003,
+            CloseCode::NoStatus => 1005,
+            CloseCode::Abnormal => 1006,
+            CloseCode::InvalidPayload => 1007,
+            CloseCode::PolicyViolation => 1008,
+            CloseCode::TooBig => 1009,
+            CloseCode::InternalError => 1011,
+            CloseCode::Other(code) => *code
+        }
+    }
+
+    // Codes that are reserved, or only mean something locally, must not appear in a close frame.
+    fn may_be_sent(code: u16) -> bool {
+        matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+    }
+}
+
+impl From<u16> for CloseCode {
+    fn from(code: u16) -> Self {
+        match code {
+            1000 => CloseCode::Normal,
+            1001 => CloseCode::GoingAway,
+            1002 => CloseCode::ProtocolError,
+            1003 => CloseCode::Unsupported,
+            1005 => CloseCode::NoStatus,
+            1006 => CloseCode::Abnormal,
+            1007 => CloseCode::InvalidPayload,
+            1008 => CloseCode::PolicyViolation,
+            1009 => CloseCode::TooBig,
+            1011 => CloseCode::InternalError,
+            code => CloseCode::Other(code)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong
+}
+
+impl Opcode {
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xA => Some(Opcode::Pong),
+            _ => None
+        }
+    }
+
+    fn bits(&self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA
+        }
+    }
+
+    fn is_control(&self) -> bool {
+        self.bits() & 0x8 != 0
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Frame {
+    fin: bool,
+    opcode: Opcode,
+    payload: Vec<u8>
+}
+
+impl Frame {
+    fn new(opcode: Opcode, payload: Vec<u8>) -> Self {
+        Frame { fin: true, opcode, payload }
+    }
+
+    /// Reads a frame a client sent from the start of `data`, and returns it with the number of bytes it took up.
+    /// Returns `None` while the frame isn't complete yet, and the code to close the connection with if it's invalid.
+    fn parse(data: &[u8], max_payload: usize) -> Result<Option<(Frame, usize)>, CloseCode> {
+        let [first, second, ..] = *data else {
+            return Ok(None);
+        };
+        // Extensions would have to be agreed on during the handshake, and there are none, so the reserved bits have to be 0.
+        if first & 0x70 != 0 {
+            return Err(CloseCode::ProtocolError);
+        }
+        let fin = first & 0x80 != 0;
+        let opcode = Opcode::from_bits(first & 0x0F).ok_or(CloseCode::ProtocolError)?;
+        // Clients have to mask every frame, so that a script can't make the bytes on the wire look like a request to a proxy.
+        if second & 0x80 == 0 {
+            return Err(CloseCode::ProtocolError);
+        }
+        let (length, start) = match second & 0x7F {
+            126 if data.len() >= 4 => (u64::from(u16::from_be_bytes([data[2], data[3]])), 4),
+            127 if data.len() >= 10 => (u64::from_be_bytes(data[2..10].try_into().unwrap_or_default()), 10),
+            126 | 127 => return Ok(None),
+            length => (u64::from(length), 2)
+        };
+        if opcode.is_control() && (!fin || length > 125) {
+            return Err(CloseCode::ProtocolError);
+        }
+        // This is checked before the payload arrived, so a client can't make the server buffer a huge frame first.
+        if length > max_payload as u64 {
+            return Err(CloseCode::TooBig);
+        }
+        let length = length as usize;
+        let Some(masked) = data.get(start + 4..start + 4 + length) else {
+            return Ok(None);
+        };
+        let mask = &data[start..start + 4];
+        let payload = masked
+            .iter()
+            .zip(mask.iter().cycle())
+            .map(|(byte, mask)| byte ^ mask)
+            .collect();
+        Ok(Some((Frame { fin, opcode, payload }, start + 4 + length)))
+    }
+
+    /// Puts the frame together the way a server sends it, which is without a mask.
+    fn encode(&self) -> Vec<u8> {
+        let length = self.payload.len();
+        let mut bytes = Vec::with_capacity(length + 10);
+        bytes.push(u8::from(self.fin) << 7 | self.opcode.bits());
+        match length {
+            0..=125 => bytes.push(length as u8),
+            126..=0xFFFF => {
+                bytes.push(126);
+                bytes.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            _ => {
+                bytes.push(127);
+                bytes.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+}
+
+/// The value of `Sec-WebSocket-Accept`, which proves to the client that the server understood its `Sec-WebSocket-Key`.
+fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(GUID.as_bytes());
+    STANDARD.encode(hasher.finalize())
+}
+
+/// An open WebSocket connection, which sends messages to the client.
+///
+/// It can be cloned and kept, e.g. to push updates to a dashboard from another thread.
+/// Once the connection is closed, sending fails.
+#[derive(Clone)]
+pub struct WebSocket {
+    shared: Arc<Shared>
+}
+
+struct Shared {
+    stream: Mutex<Box<dyn Transport + Send>>,
+    peer_addr: Option<SocketAddr>,
+    path: String,
+    // Set once a close frame was sent, after which no more messages may follow.
+    closing: AtomicBool
+}
+
+impl WebSocket {
+    pub fn send(&self, message: impl Into<Message>) -> io::Result<()> {
+        if self.is_closing() {
+            return Err(io::Error::new(ErrorKind::NotConnected, "the WebSocket is closing"));
+        }
+        let frame = match message.into() {
+            Message::Text(text) => Frame::new(Opcode::Text, text.into_bytes()),
+            Message::Binary(bytes) => Frame::new(Opcode::Binary, bytes)
+        };
+        self.write(&frame)
+    }
+
+    /// Starts the closing handshake.
+    /// Nothing can be sent afterwards, but messages the client already sent still arrive, until it answers with its own close frame.
+    pub fn close(&self, code: CloseCode, reason: &str) -> io::Result<()> {
+        if self.shared.closing.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let mut payload = Vec::new();
+        if CloseCode::may_be_sent(code.code()) {
+            payload.extend_from_slice(&code.code().to_be_bytes());
+            // Control frames can't be longer than 125 bytes, so a long reason is cut short, between two characters.
+            let mut end = reason.len().min(123);
+            while !reason.is_char_boundary(end) {
+                end -= 1;
+            }
+            payload.extend_from_slice(&reason.as_bytes()[..end]);
+        }
+        self.write(&Frame::new(Opcode::Close, payload))
+    }
+
+    /// Whether a close frame was sent already, by `close` or in answer to the client's.
+    pub fn is_closing(&self) -> bool {
+        self.shared.closing.load(Ordering::SeqCst)
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.shared.peer_addr
+    }
+
+    /// The path the WebSocket was opened on, e.g. `/live/orders`.
+    pub fn path(&self) -> &str {
+        &self.shared.path
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Box<dyn Transport + Send>> {
+        self.shared.stream
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self, frame: &Frame) -> io::Result<()> {
+        let mut stream = self.lock();
+        stream.write_all(&frame.encode())?;
+        stream.flush()
+    }
+
+    // Reads what already arrived without waiting for more. Returns `false` once the client closed the connection.
+    fn read_available(&self, buffer: &mut Vec<u8>) -> io::Result<bool> {
+        let mut stream = self.lock();
+        stream.socket().set_nonblocking(true)?;
+        let mut chunk = [0; 8192];
+        let mut total = 0;
+        let result = loop {
+            if total >= READ_LIMIT {
+                break Ok(true);
+            }
+            match stream.read(&mut chunk) {
+                Ok(0) => break Ok(false),
+                Ok(read) => {
+                    buffer.extend_from_slice(&chunk[..read]);
+                    total += read;
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break Ok(true),
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => break Err(error)
+            }
+        };
+        // Senders on other threads rely on writes blocking until the write timeout.
+        stream.socket().set_nonblocking(false)?;
+        result
+    }
+}
+
+/// What a WebSocket endpoint does with its connections.
+///
+/// The callbacks of all connections of a `WebSocketRoute` run on the same thread, one after the other.
+/// They should return quickly and hand longer work to a `ThreadPool`, which can answer with a clone of the `WebSocket`.
+/// A callback that panics only costs its own connection, which is closed with `InternalError`.
+pub trait WebSocketHandler: Send + Sync + 'static {
+    fn on_open(&self, _socket: &WebSocket) {}
+
+    fn on_message(&self, socket: &WebSocket, message: Message);
+
+    /// Runs once the connection is closed by either side.
+    /// `code` is `Abnormal` if the connection was lost without a close frame, and `NoStatus` if the client didn't give a code.
+    fn on_close(&self, _socket: &WebSocket, _code: CloseCode, _reason: &str) {}
+}
+
+impl<F> WebSocketHandler for F
+    where F: Fn(&WebSocket, Message) + Send + Sync + 'static {
+    fn on_message(&self, socket: &WebSocket, message: Message) {
+        self(socket, message)
+    }
+}
+
+/// A handler that upgrades requests to WebSocket connections, e.g. `router.get("/live", WebSocketRoute::new(dashboard))`.
+///
+/// The upgrade takes the connection away from the `ThreadPool` worker that answered the request,
+/// so open WebSockets don't keep workers busy.
+/// Instead, one thread per route watches all of its connections, reads the frames that arrive and calls the `WebSocketHandler`.
+/// It's started with the first connection and stops once the route is dropped, closing the connections that are still open.
+pub struct WebSocketRoute {
+    handler: Arc<dyn WebSocketHandler>,
+    max_message_size: usize,
+    connections: Mutex<Option<Sender<Connection>>>
+}
+
+impl WebSocketRoute {
+    pub fn new<H>(handler: H) -> Self
+        where H: WebSocketHandler {
+        WebSocketRoute {
+            handler: Arc::new(handler),
+            max_message_size: 1024 * 1024,
+            connections: Mutex::new(None)
+        }
+    }
+
+    /// Closes connections with `1009` that send larger messages. The default is 1 MiB.
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    // Starts the connection thread, unless it's running already.
+    fn sender(&self) -> io::Result<Sender<Connection>> {
+        let mut sender = self.connections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(sender) = &*sender {
+            return Ok(sender.clone());
+        }
+        let (new_sender, receiver) = mpsc::channel();
+        let handler = Arc::clone(&self.handler);
+        let max_message_size = self.max_message_size;
+        thread::Builder::new()
+            .name(String::from("websocket"))
+            .spawn(move || watch(receiver, &*handler, max_message_size))?;
+        Ok(sender.insert(new_sender).clone())
+    }
+}
+
+impl Handler for WebSocketRoute {
+    fn handle(&self, request: &Request, _params: &Params) -> Response {
+        let key = match handshake_key(request) {
+            Ok(key) => key,
+            Err(response) => return response
+        };
+        let Ok(sender) = self.sender() else {
+            return Response::text(StatusCode::ServiceUnavailable, "Service Unavailable\n");
+        };
+        let path = request.path().to_string();
+        Response::new(StatusCode::SwitchingProtocols)
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "Upgrade")
+            .with_header("Sec-WebSocket-Accept", accept_key(key))
+            .with_upgrade(move |upgraded| {
+                let _ = sender.send(Connection::new(upgraded, path));
+            })
+    }
+}
+
+// Checks the opening handshake of RFC 6455, section 4.2.1, and returns the client's key.
+fn handshake_key(request: &Request) -> Result<&str, Response> {
+    if request.method() != Method::Get || request.version() != Version::Http11 {
+        return Err(Response::text(StatusCode::BadRequest, "A WebSocket has to be opened with an HTTP/1.1 GET request.\n"));
+    }
+    let headers = request.headers();
+    if !headers.has_token("Upgrade", "websocket") || !headers.has_token("Connection", "Upgrade") {
+        return Err(Response::text(StatusCode::UpgradeRequired, "This endpoint only speaks WebSocket.\n")
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "Upgrade"));
+    }
+    // Clients that want another version can try again with the one the server supports.
+    if request.header("Sec-WebSocket-Version").map(str::trim) != Some("13") {
+        return Err(Response::text(StatusCode::UpgradeRequired, "Only version 13 of WebSocket is supported.\n")
+            .with_header("Sec-WebSocket-Version", "13"));
+    }
+    let key = request
+        .header("Sec-WebSocket-Key")
+        .map(str::trim)
+        .filter(|key| STANDARD.decode(key).is_ok_and(|nonce| nonce.len() == 16));
+    key.ok_or_else(|| Response::text(StatusCode::BadRequest, "Sec-WebSocket-Key has to be 16 bytes in base64.\n"))
+}
+
+enum Poll {
+    Idle,
+    Busy,
+    Closed
+}
+
+struct Connection {
+    socket: WebSocket,
+    incoming: Vec<u8>,
+    // The type and the payload so far of a message that arrives in fragments.
+    fragments: Option<(Opcode, Vec<u8>)>,
+    closing_since: Option<Instant>,
+    guard: Option<ConnectionGuard>
+}
+
+impl Connection {
+    fn new(upgraded: Upgraded, path: String) -> Self {
+        // An idle connection is closed by the `Shutdown`, which is the only way to get the connection thread to notice it.
+        if let Some(guard) = &upgraded.guard {
+            guard.set_idle(true);
+        }
+        let peer_addr = upgraded.stream.socket().peer_addr().ok();
+        Connection {
+            socket: WebSocket {
+                shared: Arc::new(Shared {
+                    stream: Mutex::new(upgraded.stream),
+                    peer_addr,
+                    path,
+                    closing: AtomicBool::new(false)
+                })
+            },
+            incoming: upgraded.buffered,
+            fragments: None,
+            closing_since: None,
+            guard: upgraded.guard
+        }
+    }
+
+    fn poll(&mut self, handler: &dyn WebSocketHandler, max_message_size: usize) -> Poll {
+        if self.guard.as_ref().is_some_and(ConnectionGuard::is_shutting_down) {
+            self.end(handler, CloseCode::GoingAway);
+            return Poll::Closed;
+        }
+        let before = self.incoming.len();
+        let open = self.socket
+            .read_available(&mut self.incoming)
+            .unwrap_or(false);
+        let busy = self.incoming.len() > before;
+
+        let mut used = 0;
+        loop {
+            let frame = match Frame::parse(&self.incoming[used..], max_message_size) {
+                Ok(Some((frame, length))) => {
+                    used += length;
+                    frame
+                }
+                Ok(None) => break,
+                Err(code) => {
+                    self.end(handler, code);
+                    return Poll::Closed;
+                }
+            };
+            match self.receive(frame, handler, max_message_size) {
+                Ok(true) => {}
+                Ok(false) => return Poll::Closed,
+                Err(code) => {
+                    self.end(handler, code);
+                    return Poll::Closed;
+                }
+            }
+        }
+        self.incoming.drain(..used);
+
+        if !open {
+            self.closed(handler, CloseCode::Abnormal, "");
+            return Poll::Closed;
+        }
+        if self.socket.is_closing() {
+            let since = *self.closing_since.get_or_insert_with(Instant::now);
+            if since.elapsed() > CLOSE_TIMEOUT {
+                self.closed(handler, CloseCode::Abnormal, "");
+                return Poll::Closed;
+            }
+        }
+        if busy { Poll::Busy } else { Poll::Idle }
+    }
+
+    /// Handles one frame. Returns `false` once the connection is closed.
+    fn receive(&mut self, frame: Frame, handler: &dyn WebSocketHandler, max_message_size: usize) -> Result<bool, CloseCode> {
+        match frame.opcode {
+            Opcode::Ping if !self.socket.is_closing() => {
+                let _ = self.socket.write(&Frame::new(Opcode::Pong, frame.payload));
+            }
+            Opcode::Ping | Opcode::Pong => {}
+            Opcode::Close => {
+                let (code, reason) = close_reason(&frame.payload)?;
+                // If the server started closing, this is the client's answer. Otherwise the close frame is echoed, as the RFC asks.
+                let _ = self.socket.close(code, "");
+                self.closed(handler, code, &reason);
+                return Ok(false);
+            }
+            // A new message can't start while the fragments of another one are still arriving.
+            Opcode::Text | Opcode::Binary if self.fragments.is_some() => return Err(CloseCode::ProtocolError),
+            Opcode::Text | Opcode::Binary if frame.fin => self.deliver(frame.opcode, frame.payload, handler)?,
+            Opcode::Text | Opcode::Binary => self.fragments = Some((frame.opcode, frame.payload)),
+            Opcode::Continuation => {
+                let (opcode, mut payload) = self.fragments.take().ok_or(CloseCode::ProtocolError)?;
+                if payload.len() + frame.payload.len() > max_message_size {
+                    return Err(CloseCode::TooBig);
+                }
+                payload.extend_from_slice(&frame.payload);
+                if frame.fin {
+                    self.deliver(opcode, payload, handler)?;
+                } else {
+                    self.fragments = Some((opcode, payload));
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn deliver(&self, opcode: Opcode, payload: Vec<u8>, handler: &dyn WebSocketHandler) -> Result<(), CloseCode> {
+        // Once the server said goodbye, it doesn't care about new messages anymore.
+        if self.socket.is_closing() {
+            return Ok(());
+        }
+        let message = match opcode {
+            Opcode::Text => Message::Text(String::from_utf8(payload).map_err(|_| CloseCode::InvalidPayload)?),
+            _ => Message::Binary(payload)
+        };
+        if contained(|| handler.on_message(&self.socket, message)) {
+            Ok(())
+        } else {
+            Err(CloseCode::InternalError)
+        }
+    }
+
+    fn end(&self, handler: &dyn WebSocketHandler, code: CloseCode) {
+        let _ = self.socket.close(code, "");
+        self.closed(handler, code, "");
+    }
+
+    fn closed(&self, handler: &dyn WebSocketHandler, code: CloseCode, reason: &str) {
+        contained(|| handler.on_close(&self.socket, code, reason));
+    }
+}
+
+impl Drop for Connection {
+    // Clones of the `WebSocket` may still be around, so the connection is shut down instead of waiting for them to be dropped.
+    fn drop(&mut self) {
+        let mut stream = self.socket.lock();
+        stream.close();
+        let _ = stream.socket().shutdown(Direction::Both);
+    }
+}
+
+fn close_reason(payload: &[u8]) -> Result<(CloseCode, String), CloseCode> {
+    match payload {
+        [] => Ok((CloseCode::NoStatus, String::new())),
+        [first, second, reason @ ..] => {
+            let code = u16::from_be_bytes([*first, *second]);
+            if !CloseCode::may_be_sent(code) {
+                return Err(CloseCode::ProtocolError);
+            }
+            let reason = String::from_utf8(reason.to_vec()).map_err(|_| CloseCode::InvalidPayload)?;
+            Ok((CloseCode::from(code), reason))
+        }
+        [_] => Err(CloseCode::ProtocolError)
+    }
+}
+
+// Runs a callback of the handler, and returns `false` if it panicked.
+// One thread watches all connections of a route, so a panic that got through would take every one of them down, and the route with them.
+// The panic message is still printed by the panic hook, and the connection that caused it is closed with `1011`.
+// `AssertUnwindSafe` is fine for the same reason as in the `ThreadPool`: losing the other connections would be worse than a handler with half-updated state.
+fn contained<F>(f: F) -> bool
+    where F: FnOnce() {
+    panic::catch_unwind(AssertUnwindSafe(f)).is_ok()
+}
+
+// Says hello to a new connection, or closes it again if the handler panicked doing that.
+fn open(connections: &mut Vec<Connection>, connection: Connection, handler: &dyn WebSocketHandler) {
+    if contained(|| handler.on_open(&connection.socket)) {
+        connections.push(connection);
+    } else {
+        connection.end(handler, CloseCode::InternalError);
+    }
+}
+
+// Watches the connections of one route, until the route is dropped.
+fn watch(receiver: Receiver<Connection>, handler: &dyn WebSocketHandler, max_message_size: usize) {
+    let mut connections: Vec<Connection> = Vec::new();
+    loop {
+        // Without connections there's nothing to read, so the thread sleeps until the next one is opened.
+        if connections.is_empty() {
+            let Ok(connection) = receiver.recv() else {
+                return;
+            };
+            open(&mut connections, connection, handler);
+        }
+        loop {
+            match receiver.try_recv() {
+                Ok(connection) => open(&mut connections, connection, handler),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    for connection in &connections {
+                        connection.end(handler, CloseCode::GoingAway);
+                    }
+                    return;
+                }
+            }
+        }
+
+        let mut busy = false;
+        connections.retain_mut(|connection| match connection.poll(handler, max_message_size) {
+            Poll::Idle => true,
+            Poll::Busy => {
+                busy = true;
+                true
+            }
+            Poll::Closed => false
+        });
+        if !busy {
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn masked(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xFA, 0x21, 0x3D];
+        let mut bytes = vec![first];
+        match payload.len() {
+            0..=125 => bytes.push(0x80 | payload.len() as u8),
+            length => {
+                bytes.push(0x80 | 126);
+                bytes.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+        }
+        bytes.extend_from_slice(&mask);
+        bytes.extend(payload.iter().zip(mask.iter().cycle()).map(|(byte, mask)| byte ^ mask));
+        bytes
+    }
+
+    #[test]
+    fn computes_the_accept_key() {
+        // The example from RFC 6455, section 1.3.
+        assert_eq!("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=", accept_key("dGhlIHNhbXBsZSBub25jZQ=="));
+    }
+
+    #[test]
+    fn parses_masked_frames_in_pieces() {
+        let mut data = masked(0x01, b"Hello");
+        let long = vec![b'x'; 300];
+        data.extend(masked(0x82, &long));
+
+        // Nothing can be read until the whole first frame is there.
+        for end in 0..11 {
+            assert_eq!(Ok(None), Frame::parse(&data[..end], 1024));
+        }
+        let (frame, used) = Frame::parse(&data, 1024).unwrap().unwrap();
+        assert_eq!(Frame { fin: false, opcode: Opcode::Text, payload: b"Hello".to_vec() }, frame);
+        let (frame, rest) = Frame::parse(&data[used..], 1024).unwrap().unwrap();
+        assert_eq!(Frame::new(Opcode::Binary, long), frame);
+        assert_eq!(data.len(), used + rest);
+
+        assert_eq!(vec![0x81, 0x02, b'h', b'i'], Frame::new(Opcode::Text, b"hi".to_vec()).encode());
+        assert_eq!([0x82, 126, 0x01, 0x2C], Frame::new(Opcode::Binary, vec![0; 300]).encode()[..4]);
+    }
+
+    #[test]
+    fn rejects_invalid_frames() {
+        // Unmasked, with a reserved bit, with an unknown opcode, a fragmented ping and a ping that's too long.
+        assert_eq!(Err(CloseCode::ProtocolError), Frame::parse(&[0x81, 0x00], 1024));
+        assert_eq!(Err(CloseCode::ProtocolError), Frame::parse(&masked(0xC1, b""), 1024));
+        assert_eq!(Err(CloseCode::ProtocolError), Frame::parse(&masked(0x83, b""), 1024));
+        assert_eq!(Err(CloseCode::ProtocolError), Frame::parse(&masked(0x09, b""), 1024));
+        assert_eq!(Err(CloseCode::ProtocolError), Frame::parse(&masked(0x89, &[0; 126]), 1024));
+        assert_eq!(Err(CloseCode::TooBig), Frame::parse(&masked(0x82, &[0; 200])[..4], 100));
+
+        assert_eq!(Err(CloseCode::ProtocolError), close_reason(&1005u16.to_be_bytes()));
+        assert_eq!(Ok((CloseCode::Other(4000), String::from("bye"))), close_reason(b"\x0F\xA0bye"));
+    }
+}
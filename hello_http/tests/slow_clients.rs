@@ -0,0 +1,54 @@
+use hello_http::{ConnectionOptions, Params, Request, Response, Router, Server, StatusCode, ThreadPool};
+use std::{
+    io::prelude::*,
+    net::{TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant}
+};
+
+#[test]
+fn slow_clients_dont_starve_the_pool() {
+    let mut router = Router::new();
+    router.get("/", |_: &Request, _: &Params| Response::text(StatusCode::Ok, "hello"));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let options = ConnectionOptions {
+        idle_timeout: Duration::from_millis(300),
+        header_timeout: Duration::from_millis(300),
+        ..ConnectionOptions::default()
+    };
+    let server = Server::new(router, ThreadPool::new(2)).with_options(options);
+    let shutdown = server.shutdown_handle();
+    let running = thread::spawn(move || server.run(listener));
+
+    // One client never says anything, the other one sends a byte every now and then, which used to hold a worker forever.
+    let silent = TcpStream::connect(address).unwrap();
+    let trickling = thread::spawn(move || {
+        let mut stream = TcpStream::connect(address).unwrap();
+        for byte in b"GET / HT" {
+            stream.write_all(&[*byte]).unwrap();
+            thread::sleep(Duration::from_millis(30));
+        }
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        response
+    });
+    thread::sleep(Duration::from_millis(100));
+
+    let start = Instant::now();
+    let mut client = TcpStream::connect(address).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    client.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.ends_with("hello"));
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    assert!(trickling.join().unwrap().starts_with("HTTP/1.1 408 Request Timeout"));
+    // The silent client was only idle, so its connection is closed without a response.
+    assert_eq!(0, (&silent).read(&mut [0; 1]).unwrap());
+
+    shutdown.trigger();
+    running.join().unwrap().unwrap();
+}
@@ -0,0 +1,110 @@
+#![cfg(feature = "tls")]
+
+use hello_http::{ConnectionOptions, Params, Request, Response, Router, Server, StatusCode, ThreadPool, TlsConfig, TlsError};
+use rustls::{
+    crypto::ring,
+    pki_types::ServerName,
+    ClientConfig,
+    ClientConnection,
+    RootCertStore,
+    StreamOwned
+};
+use std::{
+    io::prelude::*,
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant}
+};
+
+fn get<S>(stream: &mut S) -> String
+    where S: Read + Write {
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+fn connect_tls(address: SocketAddr, certificate: &rcgen::Certificate) -> StreamOwned<ClientConnection, TcpStream> {
+    // The client only trusts the certificate that was made for this test.
+    let mut roots = RootCertStore::empty();
+    roots.add(certificate.der().clone()).unwrap();
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let connection = ClientConnection::new(Arc::new(config), ServerName::try_from("localhost").unwrap()).unwrap();
+    StreamOwned::new(connection, TcpStream::connect(address).unwrap())
+}
+
+#[test]
+fn serves_http_and_https_at_once() {
+    let self_signed = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let tls = TlsConfig::from_pem(self_signed.cert.pem().as_bytes(), self_signed.key_pair.serialize_pem().as_bytes()).unwrap();
+
+    let mut router = Router::new();
+    router.get("/", |_: &Request, _: &Params| Response::text(StatusCode::Ok, "hello"));
+    let http = TcpListener::bind("127.0.0.1:0").unwrap();
+    let https = TcpListener::bind("127.0.0.1:0").unwrap();
+    let (http_address, https_address) = (http.local_addr().unwrap(), https.local_addr().unwrap());
+    let server = Server::new(router, ThreadPool::new(2)).with_https(https, tls);
+    let shutdown = server.shutdown_handle();
+    let running = thread::spawn(move || server.run(http));
+
+    let plain = get(&mut TcpStream::connect(http_address).unwrap());
+    assert!(plain.starts_with("HTTP/1.1 200 OK"));
+    assert!(plain.ends_with("hello"));
+    let encrypted = get(&mut connect_tls(https_address, &self_signed.cert));
+    assert!(encrypted.starts_with("HTTP/1.1 200 OK"));
+    assert!(encrypted.ends_with("hello"));
+
+    shutdown.trigger();
+    running.join().unwrap().unwrap();
+}
+
+#[test]
+fn gives_up_on_handshakes_that_trickle_in() {
+    let self_signed = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let tls = TlsConfig::from_pem(self_signed.cert.pem().as_bytes(), self_signed.key_pair.serialize_pem().as_bytes()).unwrap();
+
+    let https = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = https.local_addr().unwrap();
+    let options = ConnectionOptions {
+        header_timeout: Duration::from_millis(300),
+        ..ConnectionOptions::default()
+    };
+    let server = Server::new(Router::new(), ThreadPool::new(1))
+        .with_options(options)
+        .with_https(https, tls);
+    let shutdown = server.shutdown_handle();
+    let running = thread::spawn(move || server.serve());
+
+    // The record header announces a 512 byte handshake message, which then arrives one byte every 50 ms.
+    let mut client = TcpStream::connect(address).unwrap();
+    client.write_all(&[0x16, 0x03, 0x01, 0x02, 0x00]).unwrap();
+    let mut trickle = client.try_clone().unwrap();
+    thread::spawn(move || {
+        while trickle.write_all(&[0]).is_ok() {
+            thread::sleep(Duration::from_millis(50));
+        }
+    });
+
+    let started = Instant::now();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    // Whether the server's close arrives as the end of the stream or as a reset depends on the timing.
+    assert!(matches!(client.read(&mut [0; 64]), Ok(0) | Err(_)));
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    shutdown.trigger();
+    running.join().unwrap().unwrap();
+}
+
+#[test]
+fn rejects_files_without_a_key() {
+    let self_signed = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+    let certificate = self_signed.cert.pem();
+
+    assert!(matches!(TlsConfig::from_pem(certificate.as_bytes(), b""), Err(TlsError::NoPrivateKey)));
+    assert!(matches!(TlsConfig::from_pem(b"", b""), Err(TlsError::NoCertificate)));
+}
@@ -0,0 +1,160 @@
+use hello_http::{CloseCode, Message, Params, Request, Response, Router, Server, StatusCode, ThreadPool, WebSocket, WebSocketHandler, WebSocketRoute};
+use std::{
+    io::{prelude::*, BufReader},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, Sender},
+        Mutex
+    },
+    thread,
+    time::Duration
+};
+
+struct Echo {
+    closed: Mutex<Sender<(CloseCode, String)>>
+}
+
+impl WebSocketHandler for Echo {
+    fn on_message(&self, socket: &WebSocket, message: Message) {
+        let _ = socket.send(message);
+    }
+
+    fn on_close(&self, _socket: &WebSocket, code: CloseCode, reason: &str) {
+        let _ = self.closed.lock().unwrap().send((code, reason.to_string()));
+    }
+}
+
+fn masked(first: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [1, 2, 3, 4];
+    let mut frame = vec![first, 0x80 | payload.len() as u8];
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(byte, mask)| byte ^ mask));
+    frame
+}
+
+// Server frames are never masked, and the ones in this test are short.
+fn read_frame(reader: &mut impl Read) -> (u8, Vec<u8>) {
+    let mut head = [0; 2];
+    reader.read_exact(&mut head).unwrap();
+    let mut payload = vec![0; usize::from(head[1])];
+    reader.read_exact(&mut payload).unwrap();
+    (head[0], payload)
+}
+
+fn get(address: SocketAddr) -> String {
+    let mut client = TcpStream::connect(address).unwrap();
+    client.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn echoes_messages_without_holding_a_worker() {
+    let (closed, close_codes) = mpsc::channel();
+    let mut router = Router::new();
+    router
+        .get("/", |_: &Request, _: &Params| Response::text(StatusCode::Ok, "hello"))
+        .get("/echo", WebSocketRoute::new(Echo { closed: Mutex::new(closed) }));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    // A single worker would be stuck with the WebSocket, if the upgrade didn't take the connection away from it.
+    let server = Server::new(router, ThreadPool::new(1));
+    let shutdown = server.shutdown_handle();
+    let running = thread::spawn(move || server.run(listener));
+
+    let client = TcpStream::connect(address).unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    (&client).write_all(b"GET /echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n").unwrap();
+    let mut reader = BufReader::new(&client);
+    let mut head = String::new();
+    while !head.ends_with("\r\n\r\n") {
+        reader.read_line(&mut head).unwrap();
+    }
+    assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+    assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+    assert!(get(address).ends_with("hello"));
+
+    // A message in two fragments, with a ping in between.
+    let mut frames = masked(0x01, b"Hel");
+    frames.extend(masked(0x89, b"are you there?"));
+    frames.extend(masked(0x80, b"lo"));
+    (&client).write_all(&frames).unwrap();
+    assert_eq!((0x8A, b"are you there?".to_vec()), read_frame(&mut reader));
+    assert_eq!((0x81, b"Hello".to_vec()), read_frame(&mut reader));
+
+    (&client).write_all(&masked(0x88, b"\x03\xE8bye")).unwrap();
+    assert_eq!((0x88, b"\x03\xE8".to_vec()), read_frame(&mut reader));
+    assert_eq!(0, reader.read(&mut [0; 1]).unwrap());
+    assert_eq!((CloseCode::Normal, String::from("bye")), close_codes.recv_timeout(Duration::from_secs(5)).unwrap());
+
+    shutdown.trigger();
+    running.join().unwrap().unwrap();
+}
+
+#[test]
+fn refuses_requests_that_arent_upgrades() {
+    let mut router = Router::new();
+    router.get("/echo", WebSocketRoute::new(|socket: &WebSocket, message| {
+        let _ = socket.send(message);
+    }));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = Server::new(router, ThreadPool::new(1));
+    let shutdown = server.shutdown_handle();
+    let running = thread::spawn(move || server.run(listener));
+
+    let mut client = TcpStream::connect(address).unwrap();
+    client.write_all(b"GET /echo HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+
+    let mut client = TcpStream::connect(address).unwrap();
+    client.write_all(b"GET /echo HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade, close\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 8\r\n\r\n").unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    assert!(response.contains("Sec-WebSocket-Version: 13\r\n"));
+
+    shutdown.trigger();
+    running.join().unwrap().unwrap();
+}
+
+#[test]
+fn survives_handlers_that_panic() {
+    let mut router = Router::new();
+    router.get("/echo", WebSocketRoute::new(|socket: &WebSocket, message| {
+        assert!(message != Message::from("boom"), "the handler blew up");
+        let _ = socket.send(message);
+    }));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = Server::new(router, ThreadPool::new(1));
+    let shutdown = server.shutdown_handle();
+    let running = thread::spawn(move || server.run(listener));
+    let open = || {
+        let client = TcpStream::connect(address).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        (&client).write_all(b"GET /echo HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n").unwrap();
+        let mut reader = BufReader::new(client);
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            reader.read_line(&mut head).unwrap();
+        }
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        reader
+    };
+
+    let mut first = open();
+    first.get_ref().write_all(&masked(0x81, b"boom")).unwrap();
+    assert_eq!((0x88, b"\x03\xF3".to_vec()), read_frame(&mut first));
+
+    // The route still works for everyone else.
+    let mut second = open();
+    second.get_ref().write_all(&masked(0x81, b"still there?")).unwrap();
+    assert_eq!((0x81, b"still there?".to_vec()), read_frame(&mut second));
+
+    shutdown.trigger();
+    running.join().unwrap().unwrap();
+}
@@ -0,0 +1,137 @@
+use hello_http::{Scheduler, ThreadPool};
+use std::{
+    env,
+    hint,
+    num::NonZeroUsize,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+        Mutex
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant}
+};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// The thread pool from the Rust book, which hello_http started with.
+/// Every worker waits for the same channel behind one mutex, so it serves as the baseline.
+struct ChannelPool {
+    workers: Vec<JoinHandle<()>>,
+    sender: Option<Sender<Job>>
+}
+
+impl ChannelPool {
+    fn new(size: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..size)
+            .map(|_| {
+                let receiver: Arc<Mutex<Receiver<Job>>> = Arc::clone(&receiver);
+                thread::spawn(move || loop {
+                    let message = receiver.lock().unwrap().recv();
+                    match message {
+                        Ok(job) => job(),
+                        Err(_) => break
+                    }
+                })
+            })
+            .collect();
+        ChannelPool { workers, sender: Some(sender) }
+    }
+
+    fn execute(&self, job: Job) {
+        self.sender.as_ref().unwrap().send(job).unwrap();
+    }
+}
+
+impl Drop for ChannelPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+struct JobSize {
+    name: &'static str,
+    // How many rounds of busy work every job does.
+    work: u64,
+    jobs: usize
+}
+
+const JOB_SIZES: [JobSize; 4] = [
+    JobSize { name: "empty", work: 0, jobs: 200_000 },
+    JobSize { name: "tiny", work: 100, jobs: 200_000 },
+    JobSize { name: "small", work: 10_000, jobs: 20_000 },
+    JobSize { name: "large", work: 1_000_000, jobs: 500 }
+];
+
+fn busy_work(rounds: u64) -> u64 {
+    // `black_box` keeps the compiler from calculating the result ahead of time.
+    (0..rounds).fold(0, |sum, round| hint::black_box(sum ^ round.wrapping_mul(31)))
+}
+
+/// Hands out all jobs through `execute` and returns how long it took until every one of them was done.
+fn measure<E>(size: &JobSize, execute: E) -> Duration
+    where E: Fn(Job) {
+    let (done, finished) = mpsc::channel();
+    let start = Instant::now();
+    for _ in 0..size.jobs {
+        let done = done.clone();
+        let work = size.work;
+        execute(Box::new(move || {
+            hint::black_box(busy_work(work));
+            let _ = done.send(());
+        }));
+    }
+    for _ in 0..size.jobs {
+        finished.recv().unwrap();
+    }
+    start.elapsed()
+}
+
+fn hello_pool(workers: usize, scheduler: Scheduler) -> ThreadPool {
+    ThreadPool::builder()
+        .min_workers(workers)
+        .max_workers(workers)
+        .scheduler(scheduler)
+        .build()
+        .expect("failed to start the workers")
+        // Workers starting and stopping would only get in the way of the table.
+        .with_logger(|_, _| {})
+}
+
+fn main() {
+    // The number of workers can be passed as the first argument, e.g. `cargo run --release -p pool_bench -- 16`.
+    let workers = env::args()
+        .nth(1)
+        .and_then(|argument| argument.parse().ok())
+        .unwrap_or_else(|| thread::available_parallelism().map_or(4, NonZeroUsize::get));
+    println!("{workers} workers");
+    println!("{:<8} {:>8} {:>14} {:>14} {:>14}", "job size", "jobs", "channel", "shared queue", "work stealing");
+
+    for size in &JOB_SIZES {
+        let channel = ChannelPool::new(workers);
+        let channel_time = measure(size, |job| channel.execute(job));
+        drop(channel);
+
+        let shared = hello_pool(workers, Scheduler::SharedQueue);
+        let shared_time = measure(size, |job| shared.execute(job));
+        drop(shared);
+
+        let stealing = hello_pool(workers, Scheduler::WorkStealing);
+        let stealing_time = measure(size, |job| stealing.execute(job));
+        drop(stealing);
+
+        println!(
+            "{:<8} {:>8} {:>12.1}ms {:>12.1}ms {:>12.1}ms",
+            size.name,
+            size.jobs,
+            channel_time.as_secs_f64() * 1000.0,
+            shared_time.as_secs_f64() * 1000.0,
+            stealing_time.as_secs_f64() * 1000.0
+        );
+    }
+}